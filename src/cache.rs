@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use clap::ValueEnum;
+
+use crate::incremental::Previous;
+use crate::prefilter::{get_partial_hash, PARTIAL_PREFIX};
+use crate::{get_file_hash, HashType, MUSH_DIR};
+
+/// File inside the destination's mush directory holding cached hashes
+const HASH_CACHE: &str = "hashes";
+
+/// Prefix of the extended attributes holding cached hashes
+const XATTR_PREFIX: &str = "user.mush.";
+
+/// Files modified this recently are not cached, a change within the same
+/// mtime tick would go unnoticed
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// Where `scan` keeps the hashes of files it has read, so unchanged files are
+/// not read again
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum HashCache {
+    /// Hash every file again
+    #[default]
+    Off,
+    /// A database in the destination's .mush folder
+    Dst,
+    /// A user.mush.* extended attribute on each file
+    Xattr,
+}
+
+/// What a cached hash was taken of, the file is unchanged while it matches
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl Stamp {
+    #[cfg(unix)]
+    fn of(path: &Path) -> Option<Stamp> {
+        use std::os::unix::fs::MetadataExt;
+        let meta = std::fs::metadata(path).ok()?;
+        let settled = meta
+            .modified()
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .is_some_and(|age| age >= SETTLE);
+        settled.then_some(Stamp {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+        })
+    }
+
+    //Files have no stable identity to key them by
+    #[cfg(not(unix))]
+    fn of(_path: &Path) -> Option<Stamp> {
+        None
+    }
+
+    /// Fields that must still match for a hash to be reused
+    fn version(&self) -> String {
+        format!("{} {} {}", self.size, self.mtime, self.mtime_nsec)
+    }
+}
+
+/// Cached hashes keyed by device, inode and what was hashed, with the
+/// stamp of the file when it was hashed
+type Entries = HashMap<(u64, u64, String), (Stamp, String)>;
+
+/// Hashes of files read by earlier scans
+pub(crate) struct Cache {
+    store: HashCache,
+    path: PathBuf,
+    //Entries loaded from the database, and the ones used or added this scan
+    loaded: Entries,
+    kept: Mutex<Entries>,
+    //Hashes recorded by the scan being repeated, checked before hashing
+    previous: Previous,
+}
+
+impl Cache {
+    /// Open the cache of the scan into dst, falling back on the hashes of an
+    /// earlier scan before hashing
+    pub(crate) fn open(store: HashCache, dst: &str, previous: Previous) -> Cache {
+        let path = Path::new(dst).join(MUSH_DIR).join(HASH_CACHE);
+        let loaded = match store {
+            HashCache::Dst => load(&path),
+            _ => HashMap::new(),
+        };
+        Cache {
+            store,
+            path,
+            loaded,
+            kept: Mutex::new(HashMap::new()),
+            previous,
+        }
+    }
+
+    /// Hash of the file at path, kind naming how it is hashed, taken from the
+    /// cache while the file is unchanged and computed otherwise
+    pub(crate) fn hash(&self, path: &Path, kind: &str, compute: impl FnOnce() -> String) -> String {
+        let compute = || self.previous.hash(path, kind).unwrap_or_else(compute);
+        let stamp = match (self.store, Stamp::of(path)) {
+            (HashCache::Off, _) | (_, None) => return compute(),
+            (_, Some(stamp)) => stamp,
+        };
+        let key = (stamp.dev, stamp.ino, kind.to_owned());
+        let cached = match self.store {
+            HashCache::Xattr => get_xattr(path, kind, &stamp),
+            _ => self
+                .loaded
+                .get(&key)
+                .filter(|(then, _)| *then == stamp)
+                .map(|(_, hash)| hash.to_owned()),
+        };
+        let hash = cached.unwrap_or_else(|| {
+            let hash = compute();
+            if self.store == HashCache::Xattr {
+                set_xattr(path, kind, &stamp, &hash);
+            }
+            hash
+        });
+        self.kept
+            .lock()
+            .expect("Expected cache lock")
+            .insert(key, (stamp, hash.to_owned()));
+        hash
+    }
+
+    /// Hash of the whole content of the file at path
+    pub(crate) fn full_hash(&self, path: &Path, hash_type: HashType) -> String {
+        self.hash(path, &hash_type.to_string(), || {
+            get_file_hash(path, Some(hash_type))
+        })
+    }
+
+    /// Hash of the size and the ends of the file at path
+    pub(crate) fn partial_hash(&self, path: &Path, hash_type: HashType) -> String {
+        let kind = format!("{}{}", PARTIAL_PREFIX, hash_type);
+        self.hash(path, &kind, || get_partial_hash(path, hash_type))
+    }
+
+    /// Write the database back, keeping only the files seen by this scan. A
+    /// destination that does not exist yet is not created for it.
+    pub(crate) fn save(self) {
+        let dst = self.path.parent().and_then(Path::parent);
+        if self.store != HashCache::Dst || !dst.is_some_and(Path::is_dir) {
+            return;
+        }
+        let kept = self.kept.into_inner().expect("Expected cache lock");
+        if let Err(e) = save(&self.path, &kept) {
+            warning!("Failed to save hash cache {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Read the database, a line per hash: dev ino size mtime mtime_nsec kind hash
+fn load(path: &Path) -> Entries {
+    let mut entries = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return entries,
+    };
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [dev, ino, size, mtime, mtime_nsec, kind, hash] = fields[..] else {
+            continue;
+        };
+        let stamp = (|| {
+            Some(Stamp {
+                dev: dev.parse().ok()?,
+                ino: ino.parse().ok()?,
+                size: size.parse().ok()?,
+                mtime: mtime.parse().ok()?,
+                mtime_nsec: mtime_nsec.parse().ok()?,
+            })
+        })();
+        //A damaged line only costs hashing that file again
+        if let Some(stamp) = stamp {
+            entries.insert(
+                (stamp.dev, stamp.ino, kind.to_owned()),
+                (stamp, hash.to_owned()),
+            );
+        }
+    }
+    entries
+}
+
+fn save(path: &Path, entries: &Entries) -> std::io::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let mut lines: Vec<String> = entries
+        .iter()
+        .map(|((dev, ino, kind), (stamp, hash))| {
+            format!("{} {} {} {} {}", dev, ino, stamp.version(), kind, hash)
+        })
+        .collect();
+    lines.sort();
+
+    //Written aside and renamed so an interrupted scan leaves the old cache
+    let partial = path.with_extension("tmp");
+    let mut file = File::create(&partial)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(partial, path)
+}
+
+/// Name of the extended attribute holding hashes of kind
+fn xattr_name(kind: &str) -> String {
+    format!("{}{}", XATTR_PREFIX, kind.replace(':', "-"))
+}
+
+/// Hash stored on the file, if it was stored while the file looked as it does
+#[cfg(unix)]
+fn get_xattr(path: &Path, kind: &str, stamp: &Stamp) -> Option<String> {
+    let value = xattr::get(path, xattr_name(kind)).ok()??;
+    let value = String::from_utf8(value).ok()?;
+    let (version, hash) = value.rsplit_once(' ')?;
+    (version == stamp.version()).then(|| hash.to_owned())
+}
+
+#[cfg(unix)]
+fn set_xattr(path: &Path, kind: &str, stamp: &Stamp, hash: &str) {
+    let value = format!("{} {}", stamp.version(), hash);
+    //Read only files and filesystems without attributes are hashed every time
+    if let Err(e) = xattr::set(path, xattr_name(kind), value.as_bytes()) {
+        debug!("Could not cache hash of {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+fn get_xattr(_path: &Path, _kind: &str, _stamp: &Stamp) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn set_xattr(_path: &Path, _kind: &str, _stamp: &Stamp, _hash: &str) {}
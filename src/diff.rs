@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::{manifest_links, Manifest, MushActionError, MushLink};
+
+/// How an entry differs between two manifests
+pub enum EntryChange {
+    /// Entry only in the second manifest
+    Added(MushLink),
+    /// Entry only in the first manifest
+    Removed(MushLink),
+    /// Entry for the same file whose action, hash or dst changed
+    Changed(MushLink, MushLink),
+}
+
+/// Every entry that differs between two manifests, ordered by path
+pub struct ManifestDiff {
+    pub changes: Vec<EntryChange>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Compare two manifests entry by entry, matching entries by the file they read
+pub fn diff(a: &Manifest, b: &Manifest) -> Result<ManifestDiff, MushActionError> {
+    let mut entries: BTreeMap<PathBuf, (Option<MushLink>, Option<MushLink>)> = BTreeMap::new();
+    for link in manifest_links(a)? {
+        let key = link.target().to_path_buf();
+        entries.entry(key).or_default().0 = Some(link);
+    }
+    for link in manifest_links(b)? {
+        let key = link.target().to_path_buf();
+        entries.entry(key).or_default().1 = Some(link);
+    }
+
+    let changes = entries
+        .into_values()
+        .filter_map(|entry| match entry {
+            (Some(a), None) => Some(EntryChange::Removed(a)),
+            (None, Some(b)) => Some(EntryChange::Added(b)),
+            (Some(a), Some(b)) if a.action != b.action || a.hash != b.hash || a.dst != b.dst => {
+                Some(EntryChange::Changed(a, b))
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(ManifestDiff { changes })
+}
+
+impl std::fmt::Display for ManifestDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No differences");
+        }
+
+        for change in &self.changes {
+            match change {
+                EntryChange::Added(b) => writeln!(
+                    f,
+                    "{} {} {} -> {}",
+                    style!("green", "+"),
+                    b.action,
+                    b.target().display(),
+                    b.dst.display()
+                )?,
+                EntryChange::Removed(a) => writeln!(
+                    f,
+                    "{} {} {} -> {}",
+                    style!("red", "-"),
+                    a.action,
+                    a.target().display(),
+                    a.dst.display()
+                )?,
+                EntryChange::Changed(a, b) => {
+                    writeln!(f, "{} {}", style!("yellow", "~"), a.target().display())?;
+                    if a.action != b.action {
+                        writeln!(f, "    action {} -> {}", a.action, b.action)?;
+                    }
+                    if a.hash != b.hash {
+                        writeln!(f, "    hash {} -> {}", a.hash, b.hash)?;
+                    }
+                    if a.dst != b.dst {
+                        writeln!(f, "    dst {} -> {}", a.dst.display(), b.dst.display())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::manifest::path_bytes;
+use crate::{encode_path, manifest_links, Manifest, MushAction, MushActionError, MushMode};
+
+/// Path as a single quoted shell word, any byte but NUL survives single quotes
+fn quote(path: &Path) -> Vec<u8> {
+    let mut word = vec![b'\''];
+    for &b in path_bytes(path).iter() {
+        match b {
+            b'\'' => word.extend_from_slice(b"'\\''"),
+            _ => word.push(b),
+        }
+    }
+    word.push(b'\'');
+    word
+}
+
+/// Append a command line made of plain arguments and quoted paths
+fn command(script: &mut Vec<u8>, args: &[&str], paths: &[&Path]) {
+    script.extend_from_slice(args.join(" ").as_bytes());
+    for path in paths {
+        script.push(b' ');
+        script.extend(quote(path));
+    }
+    script.push(b'\n');
+}
+
+/// Append the commands that put the file at src in place at dst
+fn transfer(
+    script: &mut Vec<u8>,
+    made: &mut HashSet<Vec<u8>>,
+    src: &Path,
+    dst: &Path,
+    mode: MushMode,
+) {
+    if let Some(parent) = dst.parent().filter(|p| !p.as_os_str().is_empty()) {
+        //Each directory is created once, before the first file that needs it
+        if made.insert(path_bytes(parent).into_owned()) {
+            command(script, &["mkdir", "-p", "--"], &[parent]);
+        }
+    }
+    match mode {
+        MushMode::Copy => command(script, &["cp", "-p", "--"], &[src, dst]),
+        MushMode::Move => command(script, &["mv", "--"], &[src, dst]),
+    }
+}
+
+/// Turn a manifest into a POSIX shell script that applies it.
+///
+/// Add, Update and Retrieve entries become `mkdir -p` with `cp -p` or `mv`,
+/// Remove entries become `rm`. Entries that move no file, Skip, Collision,
+/// Ignore and Conflict, are kept as comments so the script reads as the
+/// whole plan. Paths are single quoted as raw bytes, paths in comments are
+/// escaped as they are in the manifest.
+pub fn export(manifest: &Manifest, mode: MushMode) -> Result<Vec<u8>, MushActionError> {
+    let mut script = b"#!/bin/sh\n# Generated by mush, review before running\nset -eu\n".to_vec();
+    let mut made = HashSet::new();
+
+    for link in manifest_links(manifest)? {
+        let (src, dst) = (encode_path(&link.src), encode_path(&link.dst));
+        match link.action {
+            MushAction::Add | MushAction::Update => {
+                script.extend_from_slice(format!("\n# {} {}\n", link.action, src).as_bytes());
+                transfer(&mut script, &mut made, &link.src, &link.dst, mode);
+            }
+            MushAction::Retreive => {
+                script.extend_from_slice(format!("\n# {} {}\n", link.action, dst).as_bytes());
+                transfer(&mut script, &mut made, &link.dst, &link.src, mode);
+            }
+            MushAction::Remove => {
+                let target = link.target();
+                script.extend_from_slice(
+                    format!("\n# {} {}\n", link.action, encode_path(target)).as_bytes(),
+                );
+                command(&mut script, &["rm", "--"], &[target]);
+            }
+            MushAction::Skip => {
+                script.extend_from_slice(
+                    format!("\n# {} {} duplicates {}\n", link.action, src, dst).as_bytes(),
+                );
+            }
+            MushAction::Collision => script.extend_from_slice(
+                format!(
+                    "\n# {} {} -> {} hash collision, review and copy by hand\n",
+                    link.action, src, dst
+                )
+                .as_bytes(),
+            ),
+            MushAction::Ignore | MushAction::Conflict => {
+                script.extend_from_slice(
+                    format!("\n# {} {} -> {}\n", link.action, src, dst).as_bytes(),
+                );
+            }
+        }
+    }
+    Ok(script)
+}
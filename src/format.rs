@@ -0,0 +1,245 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{check_roots, check_version, path_bytes, path_from_bytes, Entries};
+use crate::roots::link_from_roots;
+use crate::{FileMeta, ManifestHeader, MushAction, MushActionError, MushLink};
+
+/// How a manifest file is laid out
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    /// Comma separated columns with a `#` header
+    #[default]
+    Csv,
+    /// One JSON object per line, the header first
+    Ndjson,
+    /// A single JSON document
+    Json,
+    /// A single TOML document
+    Toml,
+}
+
+impl ManifestFormat {
+    /// Format implied by the extension of path, CSV when it is not recognised
+    pub fn from_path(path: &Path) -> ManifestFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl") | Some("ndjson") => ManifestFormat::Ndjson,
+            Some("json") => ManifestFormat::Json,
+            Some("toml") => ManifestFormat::Toml,
+            _ => ManifestFormat::Csv,
+        }
+    }
+}
+
+/// A path as text when it is valid UTF-8, otherwise as its raw bytes
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PathField {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl PathField {
+    fn new(path: &Path) -> PathField {
+        match path.to_str() {
+            Some(text) => PathField::Text(text.to_owned()),
+            None => PathField::Bytes(path_bytes(path).into_owned()),
+        }
+    }
+
+    fn into_path(self) -> Result<PathBuf, MushActionError> {
+        match self {
+            PathField::Text(text) => Ok(PathBuf::from(text)),
+            PathField::Bytes(bytes) => path_from_bytes(bytes),
+        }
+    }
+}
+
+/// A manifest entry with named fields
+#[derive(Serialize, Deserialize)]
+pub(crate) struct LinkRecord {
+    action: MushAction,
+    hash: String,
+    src: PathField,
+    dst: PathField,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dst_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>,
+    /// Written for readers of the manifest, ignored when it is read back
+    #[serde(default, skip_deserializing)]
+    reason: String,
+}
+
+impl LinkRecord {
+    pub(crate) fn new(link: &MushLink) -> LinkRecord {
+        LinkRecord {
+            action: link.action.clone(),
+            hash: link.hash.to_owned(),
+            src: PathField::new(&link.src),
+            dst: PathField::new(&link.dst),
+            dst_hash: link.dst_hash.to_owned(),
+            size: link.meta.map(|m| m.size),
+            mtime: link.meta.map(|m| m.mtime),
+            mode: link.meta.map(|m| m.mode),
+            reason: link.reason().to_owned(),
+        }
+    }
+
+    fn into_link(self) -> Result<MushLink, MushActionError> {
+        let meta = match (self.size, self.mtime, self.mode) {
+            (Some(size), Some(mtime), Some(mode)) => Some(FileMeta { size, mtime, mode }),
+            _ => None,
+        };
+        let mut link = MushLink::new(
+            self.action,
+            self.hash,
+            self.src.into_path()?,
+            self.dst.into_path()?,
+        );
+        link.dst_hash = self.dst_hash;
+        link.meta = meta;
+        Ok(link)
+    }
+}
+
+/// Whole manifest as a single JSON or TOML document
+#[derive(Serialize, Deserialize)]
+struct Document {
+    header: ManifestHeader,
+    #[serde(default)]
+    links: Vec<LinkRecord>,
+}
+
+impl MushLink {
+    /// Short explanation of why the entry has its action
+    pub fn reason(&self) -> &'static str {
+        match self.action {
+            MushAction::Add => "not at the destination yet",
+            MushAction::Remove => "missing from the other side",
+            MushAction::Skip if self.hash.contains("[d") => "duplicate of another source file",
+            MushAction::Skip => "already up to date",
+            MushAction::Ignore => "excluded from the run",
+            MushAction::Update => "destination has different contents",
+            MushAction::Retreive => "changed at the destination",
+            MushAction::Collision => "same hash as a file with different contents",
+            MushAction::Conflict => "changed on both sides",
+        }
+    }
+}
+
+/// Write a manifest in one of the structured formats
+pub(crate) fn write(
+    writer: &mut Vec<u8>,
+    format: ManifestFormat,
+    header: &ManifestHeader,
+    links: &[MushLink],
+) -> std::io::Result<()> {
+    let records = links.iter().map(LinkRecord::new);
+    match format {
+        ManifestFormat::Ndjson => {
+            serde_json::to_writer(&mut *writer, header)?;
+            writeln!(writer)?;
+            for record in records {
+                serde_json::to_writer(&mut *writer, &record)?;
+                writeln!(writer)?;
+            }
+        }
+        ManifestFormat::Json => {
+            let document = Document {
+                header: header.clone(),
+                links: records.collect(),
+            };
+            serde_json::to_writer_pretty(&mut *writer, &document)?;
+            writeln!(writer)?;
+        }
+        ManifestFormat::Toml => {
+            let document = Document {
+                header: header.clone(),
+                links: records.collect(),
+            };
+            let text = toml::to_string(&document).map_err(std::io::Error::other)?;
+            writer.write_all(text.as_bytes())?;
+        }
+        ManifestFormat::Csv => unreachable!("CSV manifests are written by the manifest module"),
+    }
+    Ok(())
+}
+
+/// Read a manifest in one of the structured formats
+pub(crate) fn read(body: &[u8], format: ManifestFormat) -> Result<Entries, MushActionError> {
+    let invalid = |e: &dyn std::fmt::Display| MushActionError {
+        message: format!("Failed to read manifest: {}", e),
+    };
+    //Each record is kept with where it came from so problems can be reported
+    let mut errors = Vec::new();
+    let (header, records): (ManifestHeader, Vec<(String, LinkRecord)>) = match format {
+        ManifestFormat::Ndjson => {
+            let mut lines = BufReader::new(body).lines().enumerate();
+            let header: ManifestHeader = match lines.next() {
+                Some((_, line)) => serde_json::from_str(&line.map_err(|e| invalid(&e))?)
+                    .map_err(|e| invalid(&format!("line 1: {}", e)))?,
+                None => return Err(invalid(&"manifest is empty")),
+            };
+            let mut records = Vec::new();
+            for (index, line) in lines {
+                let line = line.map_err(|e| invalid(&e))?;
+                if line.is_empty() {
+                    continue;
+                }
+                let location = format!("line {}", index + 1);
+                match serde_json::from_str(&line) {
+                    Ok(record) => records.push((location, record)),
+                    Err(e) => errors.push(format!("{}: {}", location, e)),
+                }
+            }
+            (header, records)
+        }
+        ManifestFormat::Json => {
+            let document: Document =
+                serde_json::from_reader(BufReader::new(body)).map_err(|e| invalid(&e))?;
+            (document.header, numbered(document.links))
+        }
+        ManifestFormat::Toml => {
+            let mut text = String::new();
+            BufReader::new(body)
+                .read_to_string(&mut text)
+                .map_err(|e| invalid(&e))?;
+            let document: Document = toml::from_str(&text).map_err(|e| invalid(&e))?;
+            (document.header, numbered(document.links))
+        }
+        ManifestFormat::Csv => unreachable!("CSV manifests are read by the manifest module"),
+    };
+
+    check_version(header.version)?;
+    let mut links = Vec::new();
+    for (location, record) in records {
+        let link = record
+            .into_link()
+            .map_err(|e| e.message)
+            .and_then(|link| link_from_roots(&header, link))
+            .and_then(|link| check_roots(&header, &link).map(|_| link));
+        match link {
+            Ok(link) => links.push(link),
+            Err(e) => errors.push(format!("{}: {}", location, e)),
+        }
+    }
+
+    Ok((header, links, errors))
+}
+
+/// Label the links of a document by their position in it
+fn numbered(records: Vec<LinkRecord>) -> Vec<(String, LinkRecord)> {
+    records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| (format!("entry {}", index + 1), record))
+        .collect()
+}
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::prefilter::PARTIAL_PREFIX;
+use crate::{FileMeta, HashType, ManifestHeader, MushAction, MushLink};
+
+/// Seconds a file must have been left alone before the earlier scan started
+/// for its recorded hash to be trusted, a write within the same mtime second
+/// would go unnoticed
+const SETTLE_SECS: u64 = 2;
+
+/// Hashes recorded by an earlier scan, keyed by source path along with the
+/// size and mtime the file had then
+#[derive(Default)]
+pub(crate) struct Previous {
+    files: HashMap<PathBuf, (FileMeta, String)>,
+}
+
+impl Previous {
+    /// Hashes of the manifest that can be reused by a scan hashing with
+    /// hash_type, none when the manifest was hashed another way
+    pub(crate) fn new(
+        header: &ManifestHeader,
+        links: &[MushLink],
+        hash_type: HashType,
+    ) -> Previous {
+        if header.hash_type().ok() != Some(hash_type) {
+            warning!(
+                "Previous manifest was hashed with {}, every file is hashed again",
+                header.hash
+            );
+            return Previous::default();
+        }
+        let files = links
+            .iter()
+            .filter(|link| reads_src(link) && !link.hash.starts_with("blake3:"))
+            .filter_map(|link| {
+                let meta = link.meta?;
+                //Modified around the earlier scan, it may have been hashed mid write
+                (meta.mtime + SETTLE_SECS <= header.scanned).then(|| {
+                    (
+                        link.src.to_owned(),
+                        (meta, base_hash(&link.hash).to_owned()),
+                    )
+                })
+            })
+            .collect();
+        Previous { files }
+    }
+
+    /// Take in the hashes of another manifest, for files not already held
+    pub(crate) fn extend(&mut self, other: Previous) {
+        for (path, recorded) in other.files {
+            self.files.entry(path).or_insert(recorded);
+        }
+    }
+
+    /// Recorded hash of the file at path taken as kind names, full or
+    /// partial, if the file has the size and mtime it had then
+    pub(crate) fn hash(&self, path: &Path, kind: &str) -> Option<String> {
+        let (then, hash) = self.files.get(path)?;
+        if hash.starts_with(PARTIAL_PREFIX) != kind.starts_with(PARTIAL_PREFIX) {
+            return None;
+        }
+        let now = FileMeta::of(path)?;
+        (now.size == then.size && now.mtime == then.mtime).then(|| hash.to_owned())
+    }
+}
+
+/// Whether the entry was planned from a source file's content
+fn reads_src(link: &MushLink) -> bool {
+    !link.src.as_os_str().is_empty()
+        && !matches!(
+            link.action,
+            MushAction::Remove | MushAction::Retreive | MushAction::Conflict
+        )
+}
+
+/// Hash without the suffix scan gives duplicates and collisions
+fn base_hash(hash: &str) -> &str {
+    hash.split('[').next().unwrap_or(hash)
+}
+
+/// Whether two entries read the same content. Hashes taken the same way are
+/// compared, otherwise the file is taken as unchanged while its size and
+/// mtime are.
+fn same_file(a: &MushLink, b: &MushLink) -> bool {
+    let (a_hash, b_hash) = (base_hash(&a.hash), base_hash(&b.hash));
+    match a_hash.starts_with(PARTIAL_PREFIX) == b_hash.starts_with(PARTIAL_PREFIX) {
+        true => a_hash == b_hash,
+        false => match (a.meta, b.meta) {
+            (Some(a), Some(b)) => a.size == b.size && a.mtime == b.mtime,
+            _ => false,
+        },
+    }
+}
+
+/// How the source files changed between two scans
+#[derive(Debug, Default)]
+pub struct SourceChanges {
+    /// Files with the same content at the same path
+    pub unchanged: usize,
+    /// Files at paths the earlier scan did not see
+    pub added: Vec<PathBuf>,
+    /// Files whose content changed
+    pub modified: Vec<PathBuf>,
+    /// Files that are gone
+    pub deleted: Vec<PathBuf>,
+    /// Files that moved, from their old path to their new one
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Compare the source files of two scans of the same sources. A file gone
+/// from one path whose content turns up at a new path was renamed.
+pub fn source_changes(previous: &[MushLink], current: &[MushLink]) -> SourceChanges {
+    let mut files: BTreeMap<&Path, (Option<&MushLink>, Option<&MushLink>)> = BTreeMap::new();
+    for link in previous.iter().filter(|link| reads_src(link)) {
+        files.entry(&link.src).or_default().0 = Some(link);
+    }
+    for link in current.iter().filter(|link| reads_src(link)) {
+        files.entry(&link.src).or_default().1 = Some(link);
+    }
+
+    let mut changes = SourceChanges::default();
+    let mut gone = Vec::new();
+    let mut new = Vec::new();
+    for (then, now) in files.into_values() {
+        match (then, now) {
+            (Some(then), Some(now)) if same_file(then, now) => changes.unchanged += 1,
+            (Some(_), Some(now)) => changes.modified.push(now.src.to_owned()),
+            (Some(then), None) => gone.push(then),
+            (None, Some(now)) => new.push(now),
+            (None, None) => {}
+        }
+    }
+    for then in gone {
+        match new.iter().position(|now| same_file(then, now)) {
+            Some(i) => {
+                let now = new.remove(i);
+                changes
+                    .renamed
+                    .push((then.src.to_owned(), now.src.to_owned()));
+            }
+            None => changes.deleted.push(then.src.to_owned()),
+        }
+    }
+    changes.added = new.into_iter().map(|now| now.src.to_owned()).collect();
+    changes
+}
+
+impl std::fmt::Display for SourceChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (from, to) in &self.renamed {
+            writeln!(
+                f,
+                "{} {} -> {}",
+                style!("blue", ">"),
+                from.display(),
+                to.display()
+            )?;
+        }
+        for path in &self.deleted {
+            writeln!(f, "{} {}", style!("red", "-"), path.display())?;
+        }
+        for path in &self.modified {
+            writeln!(f, "{} {}", style!("yellow", "~"), path.display())?;
+        }
+        for path in &self.added {
+            writeln!(f, "{} {}", style!("green", "+"), path.display())?;
+        }
+        writeln!(
+            f,
+            "{} unchanged, {} new, {} modified, {} deleted, {} renamed",
+            self.unchanged,
+            self.added.len(),
+            self.modified.len(),
+            self.deleted.len(),
+            self.renamed.len()
+        )
+    }
+}
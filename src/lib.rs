@@ -1,14 +1,58 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::hash::Hasher;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
 
+#[macro_use]
 mod macros;
+mod cache;
+mod diff;
+mod export;
+mod format;
+mod incremental;
+mod manifest;
+mod merge;
+mod plan;
+mod prefilter;
+mod query;
+mod roots;
+mod seal;
+mod stats;
+mod sync;
+mod validate;
+mod walk;
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
+use cache::Cache;
+pub use cache::HashCache;
+pub use diff::{diff, EntryChange, ManifestDiff};
+pub use export::export;
+pub use format::ManifestFormat;
+use incremental::Previous;
+pub use incremental::{source_changes, SourceChanges};
+pub use manifest::{
+    decode_path, encode_path, read_manifest, Manifest, ManifestHeader, MANIFEST_VERSION,
+};
+use manifest::{manifest_links, write_manifest};
+pub use merge::merge;
+pub use plan::{plan, Plan};
+use prefilter::{content_keys, PARTIAL_PREFIX};
+pub use query::{query, render, Query, QueryOutput};
+use rayon::prelude::*;
+pub use roots::rebase;
+pub use seal::{check_signature, generate_key, seal};
+pub use stats::{stats, DuplicateGroup, Stats};
+pub use sync::{save_sync_state, sync};
+pub use validate::{validate, Validation};
+use walk::walk_sorted;
+
+/// Directory mush keeps its own state in, never treated as user files
+const MUSH_DIR: &str = ".mush";
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum MushMode {
     /// Copy files to destination
@@ -17,8 +61,9 @@ pub enum MushMode {
     Move,
 }
 
-struct MushActionError {
-    message: String,
+#[derive(Debug)]
+pub struct MushActionError {
+    pub message: String,
 }
 
 impl std::fmt::Display for MushActionError {
@@ -27,30 +72,40 @@ impl std::fmt::Display for MushActionError {
     }
 }
 
-#[derive(Clone)]
+impl Error for MushActionError {}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum MushAction {
-    Add,       //[+] Add new file to dest
-    Remove,    //[-] Remove file from dest
-    Skip,      //[*] Skip file from source (duplicate)
-    Ignore,    //[_] Ignore file from source
-    Update,    //[>] Update file on the dest
-    Retreive,  //[<] Retreive file from the dest
+    Add,    //[+] Add new file to dest
+    Remove, //[-] Remove file from dest
+    Skip,   //[*] Skip file from source (duplicate)
+    Ignore, //[_] Ignore file from source
+    Update, //[>] Update file on the dest
+    #[serde(rename = "retrieve")]
+    #[value(name = "retrieve")]
+    Retreive, //[<] Retreive file from the dest
     Collision, //[!] Hash collision detected - unlikely
+    Conflict, //[?] File changed on both sides since last sync
 }
 
-impl MushAction {
-    fn to_string(&self) -> String {
-        match self {
-            MushAction::Add => String::from("[+]"),
-            MushAction::Remove => String::from("[-]"),
-            MushAction::Skip => String::from("[*]"),
-            MushAction::Ignore => String::from("[_]"),
-            MushAction::Update => String::from("[>]"),
-            MushAction::Retreive => String::from("[<]"),
-            MushAction::Collision => String::from("[!]"),
-        }
+impl std::fmt::Display for MushAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let token = match self {
+            MushAction::Add => "[+]",
+            MushAction::Remove => "[-]",
+            MushAction::Skip => "[*]",
+            MushAction::Ignore => "[_]",
+            MushAction::Update => "[>]",
+            MushAction::Retreive => "[<]",
+            MushAction::Collision => "[!]",
+            MushAction::Conflict => "[?]",
+        };
+        write!(f, "{}", token)
     }
+}
 
+impl MushAction {
     fn from_string(s: &str) -> Option<MushAction> {
         match s {
             "[+]" => Some(MushAction::Add),
@@ -60,50 +115,145 @@ impl MushAction {
             "[>]" => Some(MushAction::Update),
             "[<]" => Some(MushAction::Retreive),
             "[!]" => Some(MushAction::Collision),
+            "[?]" => Some(MushAction::Conflict),
             _ => None,
         }
     }
 }
 
-pub enum Manifest {
-    File(File),
-    Map(HashMap<String, MushLink>),
-}
-
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct MushLink {
-    action: MushAction,
-    hash: String,
-    src: String,
-    dst: String,
+    pub action: MushAction,
+    pub hash: String,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    /// Hash of the file currently at dst when it is to be updated
+    pub dst_hash: Option<String>,
+    /// Metadata of the file the action reads from, or deletes
+    pub meta: Option<FileMeta>,
     duplicate_count: Option<u8>,
 }
 
-impl std::fmt::Display for MushLink {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{},{},{},{}",
-            self.action.to_string(),
-            self.hash,
-            self.src,
-            self.dst
-        )
+impl MushLink {
+    pub fn new(action: MushAction, hash: String, src: PathBuf, dst: PathBuf) -> MushLink {
+        MushLink {
+            action,
+            hash,
+            src,
+            dst,
+            dst_hash: None,
+            meta: None,
+            duplicate_count: None,
+        }
+    }
+
+    /// The file the entry is about. A Remove names only the side holding the
+    /// file to delete, leaving src empty when that file is in dst.
+    pub fn target(&self) -> &Path {
+        match self.src.as_os_str().is_empty() {
+            true => &self.dst,
+            false => &self.src,
+        }
     }
 }
 
-fn write_to_manifest(action: MushAction, hash: &str, src: &str, dst: &str, mut file: &File) {
-    if let Err(e) = writeln!(file, "{},{},{},{}", action.to_string(), hash, src, dst) {
-        eprintln!("Failed to write to duplicates file: {}", e);
+/// Size, modified time and permissions of a file at scan time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileMeta {
+    pub size: u64,
+    /// Seconds since the unix epoch
+    pub mtime: u64,
+    /// Permission bits
+    pub mode: u32,
+}
+
+impl FileMeta {
+    pub fn of(path: &Path) -> Option<FileMeta> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = match metadata.permissions().readonly() {
+            true => 0o444,
+            false => 0o644,
+        };
+        Some(FileMeta {
+            size: metadata.len(),
+            mtime,
+            mode,
+        })
     }
 }
 
-pub fn scan(src: Vec<String>, dst: String, manifest: &mut Manifest) -> &Manifest {
-    //Note: borrow manifest in future to mutate in place
-    let mut mushmap: HashMap<String, MushLink> = HashMap::new();
+/// Options controlling how `scan` builds a manifest
+#[derive(Default)]
+pub struct ScanOptions {
+    /// Hash files already in dst so matching source files become Skip entries
+    pub index_dst: bool,
+    /// Emit Remove entries for files in dst that no source file maps to
+    pub mirror: bool,
+    /// Where to send a file whose hash matches another file with different content
+    pub collision: CollisionPolicy,
+    /// Where to send a file whose dst path is already taken by different content
+    pub path_conflict: PathConflictPolicy,
+    /// Algorithm used to hash file contents, recorded in the manifest header
+    pub hash: HashType,
+    /// Key every source file by the hash of its whole content, rather than
+    /// only reading the ends of files nothing else could duplicate
+    pub full_digests: bool,
+    /// Threads walking and hashing files, 0 for one per core
+    pub threads: usize,
+    /// Where hashes of unchanged files are kept between scans
+    pub hash_cache: HashCache,
+    /// Header and entries of an earlier scan of the same sources, whose hashes
+    /// are reused for files unchanged since
+    pub previous: Option<(ManifestHeader, Vec<MushLink>)>,
+}
+
+/// How `scan` resolves source files with different content that map to the same dst path
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum PathConflictPolicy {
+    /// Place later files beneath a folder named after their source root
+    Namespace,
+    /// Add a counter suffix to the file name
+    #[default]
+    Rename,
+    /// Keep only the most recently modified file
+    Newest,
+}
 
+/// How `scan` resolves files whose hash collides with a file of different content,
+/// which is then keyed by its BLAKE3 hash whatever the policy
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CollisionPolicy {
+    /// Add a counter suffix to the file name
+    #[default]
+    Rename,
+    /// Add a short BLAKE3 digest of the contents to the file name
+    Digest,
+    /// Keep the file's own name unless another file already holds it
+    Rehash,
+    /// Stop scanning
+    Halt,
+}
+
+pub fn scan<'a>(
+    src: Vec<String>,
+    dst: String,
+    manifest: &'a mut Manifest,
+    options: &ScanOptions,
+) -> Result<&'a Manifest, MushActionError> {
     match manifest {
-        Manifest::File(_) => {
+        Manifest::File(..) => {
             info!("Scanning to mush manifest file...");
         }
         Manifest::Map(_) => {
@@ -111,175 +261,569 @@ pub fn scan(src: Vec<String>, dst: String, manifest: &mut Manifest) -> &Manifest
         }
     }
 
-    let mut i = 0;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads)
+        .build()
+        .map_err(|e| MushActionError {
+            message: format!("Failed to start worker threads: {}", e),
+        })?;
+
+    //Kept until the manifest is written, as dst files are hashed up to the end
+    let previous = match &options.previous {
+        Some((header, links)) => Previous::new(header, links, options.hash),
+        None => Previous::default(),
+    };
+    let cache = Cache::open(options.hash_cache, &dst, previous);
+
     let sources = src;
-    let mut terminal_msg = String::new();
-    for source in sources {
-        let files = WalkDir::new(&source);
-        for file in files {
-            i += 1;
-            terminal_msg = format!("\rProcessing #{}...", i);
-            print!("{}", terminal_msg);
-            std::io::stdout().flush().unwrap();
-            let file = file.expect("Expected file");
-            if file.path().is_file() {
-                let src_file = file.path().to_path_buf();
-                let src_parent_dir = file.path().parent().unwrap().to_str().unwrap();
-                let src_file_name = file.path().file_name().unwrap().to_str().unwrap();
-                let src_path_string =
-                    String::from(file.path().to_str().expect("Expected file path"));
-                let src_rel_path = file.path().strip_prefix(&source).unwrap();
-                let pb = file.path().to_path_buf();
-
-                let dst_dir_path_string = match dst.ends_with(std::path::MAIN_SEPARATOR) {
-                    true => format!("{}", dst),
-                    false => format!("{}{}", dst, std::path::MAIN_SEPARATOR),
-                };
-
-                let hash = get_file_hash(&pb, None);
-                let _hash_datetime = std::time::SystemTime::now();
-                let _created_date = file.metadata().unwrap().created().unwrap();
-                let _modified_date = file.metadata().unwrap().modified().unwrap();
-
-                if mushmap.contains_key(&hash) {
-                    if let Some(orig) = mushmap.get_mut(&hash) {
-                        terminal_msg += &yellow!(" Duplicate")[..];
-                        print!("{}", terminal_msg);
-
-                        //Get orig created date & modified date
-                        let orig_metadata = std::fs::metadata(&orig.src).unwrap();
-                        let _orig_created = orig_metadata.created().unwrap();
-                        let _orig_modified = orig_metadata.modified().unwrap();
-
-                        //Do a bit by bit file comparison
-                        let dup_file = std::path::PathBuf::from(&src_file);
-                        if compare_files(&src_file, &dup_file) {
-                            terminal_msg += &format!(
-                                " {}: {} (same as {})",
-                                yellow!("Skipped"),
-                                src_path_string,
-                                orig.src
-                            )[..];
-                            print!("{}", terminal_msg);
-                            if let Some(c) = orig.duplicate_count {
-                                orig.duplicate_count = Some(c + 1);
-                            }
-                            let hash = format!("{}[d{}]", hash, orig.duplicate_count.unwrap());
-                            let mushlink = MushLink {
-                                action: MushAction::Skip,
-                                hash: hash.to_owned(),
-                                src: src_path_string.to_owned(),
-                                dst: orig.dst.to_owned(),
-                                duplicate_count: None,
-                            };
-                            match manifest {
-                                Manifest::File(ref file) => {
-                                    write_to_manifest(
-                                        MushAction::Skip,
-                                        &hash[..],
-                                        &src_path_string[..],
-                                        &orig.dst[..],
-                                        &file,
-                                    );
-                                }
-                                Manifest::Map(ref mut map) => {
-                                    map.insert(hash, mushlink);
-                                }
-                            }
-                        } else {
-                            let s_path = style!("yellow", "{}/{}", src_parent_dir, src_file_name);
-                            terminal_msg += &format!("Collision detected: {} {})", s_path, orig.src)[..];
-                            print!("{}", terminal_msg);
-                            if let Some(c) = orig.duplicate_count {
-                                orig.duplicate_count = Some(c + 1);
-                            }
-                            let mushlink = MushLink {
-                                action: MushAction::Collision,
-                                hash: hash.to_owned(),
-                                src: src_path_string.to_owned(),
-                                dst: orig.dst.to_owned(),
-                                duplicate_count: None,
-                            };
-                            let hash = format!("{}[c{}]", hash, orig.duplicate_count.unwrap());
-                            match manifest {
-                                Manifest::File(ref file) => {
-                                    write_to_manifest(
-                                        MushAction::Collision,
-                                        &hash[..],
-                                        &src_path_string[..],
-                                        &orig.dst[..],
-                                        &file,
-                                    );
-                                }
-                                Manifest::Map(ref mut map) => {
-                                    map.insert(
-                                        format!("{}[{}]", hash, orig.duplicate_count.unwrap()),
-                                        mushlink,
-                                    );
-                                }
-                            }
-                        }
-                    } else {
-                        error!("Failed to get original link for {}", src_path_string);
+    //Every file is found before any is hashed so they can be grouped by size.
+    //Files are kept in path order so scheduling never changes which duplicate
+    //is the original.
+    let (found, keys) = pool.install(|| {
+        let found: Vec<(usize, PathBuf, Option<FileMeta>)> = sources
+            .iter()
+            .enumerate()
+            .flat_map(|(source_index, source)| {
+                walk_sorted(Path::new(source))
+                    .into_iter()
+                    .map(move |path| (source_index, path))
+            })
+            .collect::<Vec<(usize, PathBuf)>>()
+            .into_par_iter()
+            .map(|(source_index, path)| {
+                let meta = FileMeta::of(&path);
+                (source_index, path, meta)
+            })
+            .collect();
+        let candidates: Vec<(PathBuf, Option<u64>)> = found
+            .iter()
+            .map(|(_, path, meta)| (path.to_owned(), meta.map(|m| m.size)))
+            .collect();
+        //Destination files can be duplicates too when they are indexed
+        let mut indexed = Vec::new();
+        if options.index_dst && Path::new(&dst).is_dir() {
+            let files = walk_sorted(Path::new(&dst));
+            indexed.par_extend(files.into_par_iter().map(|path| {
+                let size = FileMeta::of(&path).map(|m| m.size);
+                (path, size)
+            }));
+        }
+        info!("Hashing {} files...", candidates.len());
+        let keys = content_keys(
+            &candidates,
+            &indexed,
+            options.hash,
+            options.full_digests,
+            &cache,
+        );
+        (found, keys)
+    });
+
+    let mut scanner = Scanner::new(dst, options, Some(&keys), &cache);
+    for (i, (source_index, path, meta)) in found.into_iter().enumerate() {
+        let terminal_msg = format!("\rProcessing #{}...", i + 1);
+        print!("{}", terminal_msg);
+        std::io::stdout().flush().unwrap();
+        let hash = keys[&path].to_owned();
+        let source = &sources[source_index];
+        scanner.add(source_index, source, path, hash, meta, terminal_msg)?;
+    }
+
+    println!();
+
+    if let Some((_, previous)) = &options.previous {
+        let current: Vec<MushLink> = scanner.links.iter().map(|(_, l)| l.clone()).collect();
+        info!("Changes since the last scan:");
+        print!("{}", source_changes(previous, &current));
+    }
+
+    scanner.finish(&sources, manifest)?;
+    cache.save();
+    Ok(manifest)
+}
+
+/// Dedup, collision and path conflict state built up over every file of a scan
+struct Scanner<'o> {
+    dst: String,
+    options: &'o ScanOptions,
+    //Note: borrow manifest in future to mutate in place
+    mushmap: HashMap<String, MushLink>,
+    //Links are held back until the scan completes so earlier ones can be revised
+    links: Vec<(String, MushLink)>,
+    //Every dst path a link writes to, with the index of that link
+    claimed: HashMap<PathBuf, usize>,
+    //Dst path each skipped duplicate would have had, by the index of its link
+    homes: HashMap<usize, PathBuf>,
+    //Hashes already taken of dst files, and where the rest are looked up
+    keys: Option<&'o HashMap<PathBuf, String>>,
+    cache: &'o Cache,
+}
+
+impl<'o> Scanner<'o> {
+    /// Scanner writing beneath dst. Keys holds the hashes of the dst files that
+    /// could duplicate a source file, every dst file is hashed when it is None.
+    fn new(
+        dst: String,
+        options: &'o ScanOptions,
+        keys: Option<&'o HashMap<PathBuf, String>>,
+        cache: &'o Cache,
+    ) -> Scanner<'o> {
+        let mut mushmap: HashMap<String, MushLink> = HashMap::new();
+        if options.index_dst {
+            index_dst(&dst, &mut mushmap, keys, cache, options.hash);
+        }
+        Scanner {
+            dst,
+            options,
+            mushmap,
+            links: Vec::new(),
+            claimed: HashMap::new(),
+            homes: HashMap::new(),
+            keys,
+            cache,
+        }
+    }
+
+    /// Decide what to do with src_file, found beneath the source root at source_index
+    fn add(
+        &mut self,
+        source_index: usize,
+        source: &str,
+        src_file: PathBuf,
+        hash: String,
+        meta: Option<FileMeta>,
+        mut terminal_msg: String,
+    ) -> Result<(), MushActionError> {
+        let src_rel_path = src_file.strip_prefix(source).unwrap();
+        let pb = src_file.to_owned();
+
+        if self.mushmap.contains_key(&hash) {
+            if let Some(orig) = self.mushmap.get_mut(&hash) {
+                terminal_msg += &yellow!(" Duplicate")[..];
+                print!("{}", terminal_msg);
+
+                //Do a bit by bit file comparison against the original
+                if compare_files(&src_file, &orig.src) {
+                    terminal_msg += &format!(
+                        " {}: {} (same as {})",
+                        yellow!("Skipped"),
+                        src_file.display(),
+                        orig.src.display()
+                    )[..];
+                    print!("{}", terminal_msg);
+                    if let Some(c) = orig.duplicate_count {
+                        orig.duplicate_count = Some(c + 1);
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(1000));
+                    let hash = format!("{}[d{}]", hash, orig.duplicate_count.unwrap());
+                    let mushlink = MushLink {
+                        action: MushAction::Skip,
+                        hash: hash.to_owned(),
+                        src: src_file.to_owned(),
+                        dst: orig.dst.to_owned(),
+                        dst_hash: None,
+                        meta,
+                        duplicate_count: None,
+                    };
+                    let home = Path::new(&self.dst).join(src_rel_path);
+                    self.homes.insert(self.links.len(), home);
+                    self.links.push((hash, mushlink));
                 } else {
-                    let dst_path_string =
-                        String::from(format!("{}{}", dst_dir_path_string, src_rel_path.display()));
-                    // let s_path = style!("green", "{}", &src_path_string);
-                    // let s_hash = style!("dim,white", "{}", &hash);
-                    // println!("NEW: {}: {}", s_path, s_hash);
-
+                    let s_path = style!("yellow", "{}", src_file.display());
+                    terminal_msg +=
+                        &format!("Collision detected: {} {})", s_path, orig.src.display())[..];
+                    print!("{}", terminal_msg);
+                    if self.options.collision == CollisionPolicy::Halt {
+                        return Err(MushActionError {
+                            message: format!(
+                                "Hash collision between {} and {}",
+                                src_file.display(),
+                                orig.src.display()
+                            ),
+                        });
+                    }
+                    //Keyed by a second digest, its copies are duplicates rather than collisions
+                    let digest = get_file_hash(&pb, Some(HashType::Blake3));
+                    let hash = format!("blake3:{}", digest);
+                    if self.mushmap.contains_key(&hash) {
+                        return self.add(source_index, source, src_file, hash, meta, terminal_msg);
+                    }
+                    let natural = Path::new(&self.dst).join(src_rel_path);
+                    let free =
+                        |path: PathBuf| match self.claimed.contains_key(&path) || path.exists() {
+                            true => unique_path(&path, &self.claimed),
+                            false => path,
+                        };
+                    let dst_path = match self.options.collision {
+                        CollisionPolicy::Rename => unique_path(&natural, &self.claimed),
+                        CollisionPolicy::Digest => {
+                            free(with_suffix(&natural, &format!("_{}", &digest[..8])))
+                        }
+                        _ => free(natural),
+                    };
                     let mushlink = MushLink {
-                        action: MushAction::Add,
+                        action: MushAction::Collision,
                         hash: hash.to_owned(),
-                        src: src_path_string.to_owned(),
-                        dst: dst_path_string.to_owned(),
+                        src: src_file.to_owned(),
+                        dst: dst_path.to_owned(),
+                        dst_hash: None,
+                        meta,
+                        duplicate_count: None,
+                    };
+                    self.claimed.insert(dst_path, self.links.len());
+                    let original = MushLink {
                         duplicate_count: Some(0),
+                        ..mushlink.clone()
                     };
+                    self.mushmap.insert(hash.to_owned(), original);
+                    self.links.push((hash, mushlink));
+                }
+            } else {
+                error!("Failed to get original link for {}", src_file.display());
+            }
+        } else {
+            let natural = Path::new(&self.dst).join(src_rel_path);
+            // let s_path = style!("green", "{}", src_file.display());
+            // let s_hash = style!("dim,white", "{}", &hash);
+            // println!("NEW: {}: {}", s_path, s_hash);
 
-                    match manifest {
-                        Manifest::File(ref file) => {
-                            write_to_manifest(
-                                MushAction::Add,
-                                &hash[..],
-                                &src_path_string[..],
-                                &dst_path_string[..],
-                                file,
-                            );
+            //Another source file with different content may already be headed here
+            let mut ignored = false;
+            let dst_path = match self.claimed.get(&natural) {
+                None => natural,
+                Some(&index) => {
+                    terminal_msg += &format!(
+                        " {}: {} (same path as {})",
+                        yellow!("Path conflict"),
+                        src_file.display(),
+                        self.links[index].1.src.display()
+                    )[..];
+                    print!("{}", terminal_msg);
+                    match self.options.path_conflict {
+                        PathConflictPolicy::Namespace => {
+                            let root_name = match Path::new(source).file_name() {
+                                Some(name) => name.to_string_lossy().to_string(),
+                                None => format!("source_{}", source_index),
+                            };
+                            let namespaced =
+                                Path::new(&self.dst).join(root_name).join(src_rel_path);
+                            match self.claimed.contains_key(&namespaced) {
+                                true => unique_path(&namespaced, &self.claimed),
+                                false => namespaced,
+                            }
                         }
-                        Manifest::Map(ref mut map) => {
-                            map.insert(hash.to_owned(), mushlink.clone());
-                            //todo!("Might change manifest to vec instead of map - can warn user of skipped files");
+                        PathConflictPolicy::Rename => unique_path(&natural, &self.claimed),
+                        PathConflictPolicy::Newest => {
+                            if modified(&src_file) > modified(&self.links[index].1.src) {
+                                self.demote(index);
+                            } else {
+                                ignored = true;
+                            }
+                            natural
                         }
                     }
-
-                    mushmap.insert(hash.to_owned(), mushlink);
                 }
+            };
+
+            let (action, dst_hash) = match ignored {
+                true => (MushAction::Ignore, None),
+                false => self.place(&src_file, &hash, &dst_path),
+            };
+
+            let mushlink = MushLink {
+                action,
+                hash: hash.to_owned(),
+                src: src_file.to_owned(),
+                dst: dst_path.to_owned(),
+                dst_hash,
+                meta,
+                duplicate_count: Some(0),
+            };
+
+            //todo!("Might change manifest to vec instead of map - can warn user of skipped files");
+            if ignored {
+                self.links.push((hash.to_owned(), mushlink));
+                self.ignore(self.links.len() - 1);
+                return Ok(());
             }
+            self.claimed.insert(dst_path, self.links.len());
+            self.links.push((hash.to_owned(), mushlink.clone()));
+
+            self.mushmap.insert(hash.to_owned(), mushlink);
+        }
+        Ok(())
+    }
+
+    /// Record an Ignore entry as it is, without deciding it again
+    fn keep(&mut self, link: MushLink) {
+        self.links.push((link.hash.to_owned(), link));
+        self.ignore(self.links.len() - 1);
+    }
+
+    /// Ignore the link at index, keyed apart from the entries reading the
+    /// same content so none of them replaces it
+    fn ignore(&mut self, index: usize) {
+        let (key, link) = &mut self.links[index];
+        link.action = MushAction::Ignore;
+        link.hash = format!("{}[i{}]", link.hash, index);
+        *key = link.hash.to_owned();
+    }
+
+    /// Action for writing src, keyed by hash, to dst_path. An existing file
+    /// there is either already up to date or stale, it is hashed the same way
+    /// as src and matching partial hashes are confirmed byte by byte.
+    fn place(&self, src: &PathBuf, hash: &str, dst_path: &PathBuf) -> (MushAction, Option<String>) {
+        if !dst_path.is_file() {
+            return (MushAction::Add, None);
+        }
+        let partial = hash.starts_with(PARTIAL_PREFIX);
+        let dst_hash = match partial {
+            true => self.cache.partial_hash(dst_path, self.options.hash),
+            false => self.cache.full_hash(dst_path, self.options.hash),
+        };
+        match dst_hash == hash && (!partial || compare_files(src, dst_path)) {
+            true => (MushAction::Skip, None),
+            false => (MushAction::Update, Some(dst_hash)),
+        }
+    }
+
+    /// Ignore the link at index, which lost its dst path to a newer file. Its
+    /// duplicates must not point at the winner, so the first of them is
+    /// written to its own path in its place and the rest point there.
+    fn demote(&mut self, index: usize) {
+        let hash = self.links[index].1.hash.to_owned();
+        let lost = self.links[index].1.dst.to_owned();
+        self.mushmap.remove(&hash);
+        self.ignore(index);
+
+        let prefix = format!("{}[d", hash);
+        let duplicates: Vec<usize> = (0..self.links.len())
+            .filter(|&i| {
+                let link = &self.links[i].1;
+                link.action == MushAction::Skip
+                    && link.dst == lost
+                    && link.hash.starts_with(&prefix)
+            })
+            .collect();
+        let Some((&heir, rest)) = duplicates.split_first() else {
+            return;
+        };
+
+        let home = self.homes.remove(&heir).unwrap();
+        let dst_path = match self.claimed.contains_key(&home) {
+            true => unique_path(&home, &self.claimed),
+            false => home,
+        };
+        let src = self.links[heir].1.src.to_owned();
+        let (action, dst_hash) = self.place(&src, &hash, &dst_path);
+        warning!(
+            "{} lost {} to a newer file, {} is written to {} instead",
+            self.links[index].1.src.display(),
+            lost.display(),
+            self.links[heir].1.src.display(),
+            dst_path.display()
+        );
+        for (n, &i) in rest.iter().enumerate() {
+            let duplicate_hash = format!("{}[d{}]", hash, n + 1);
+            self.links[i].0 = duplicate_hash.to_owned();
+            self.links[i].1.hash = duplicate_hash;
+            self.links[i].1.dst = dst_path.to_owned();
         }
+        let (key, link) = &mut self.links[heir];
+        *key = hash.to_owned();
+        link.hash = hash.to_owned();
+        link.action = action;
+        link.dst = dst_path.to_owned();
+        link.dst_hash = dst_hash;
+        link.duplicate_count = Some(rest.len() as u8);
+        self.claimed.insert(dst_path, heir);
+        self.mushmap.insert(hash, link.clone());
     }
 
-    manifest
+    /// Write every link the scan decided on to the manifest
+    fn finish(self, sources: &[String], manifest: &mut Manifest) -> Result<(), MushActionError> {
+        let mut links = self.links;
+
+        //Every dst path a source file maps to, used to find dst-only files when mirroring
+        let targets: HashSet<PathBuf> = links.iter().map(|(_, l)| l.dst.to_owned()).collect();
+        if self.options.mirror {
+            links.extend(mirror_dst(
+                &self.dst,
+                &targets,
+                self.keys,
+                self.cache,
+                self.options.hash,
+            ));
+        }
+
+        let header = ManifestHeader {
+            hash: self.options.hash.to_string(),
+            ..ManifestHeader::new(sources, &self.dst)
+        };
+        write_manifest(manifest, &header, links)
+    }
+}
+
+/// Insert suffix between the file stem and extension of path
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// First counter suffixed variant of path that is neither claimed nor on disk
+fn unique_path(path: &Path, claimed: &HashMap<PathBuf, usize>) -> PathBuf {
+    (1..)
+        .map(|n| with_suffix(path, &format!("_{}", n)))
+        .find(|p| !claimed.contains_key(p) && !p.exists())
+        .unwrap()
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Remove entries for every file in dst that is not a target
+fn mirror_dst(
+    dst: &str,
+    targets: &HashSet<PathBuf>,
+    keys: Option<&HashMap<PathBuf, String>>,
+    cache: &Cache,
+    hash_type: HashType,
+) -> Vec<(String, MushLink)> {
+    let mut removals = Vec::new();
+    if !Path::new(dst).is_dir() {
+        return removals;
+    }
+
+    info!("Finding destination files to remove...");
+    for file in walk_files(dst) {
+        if targets.contains(file.path()) {
+            continue;
+        }
+        let hash = match keys.and_then(|keys| keys.get(file.path())) {
+            Some(hash) => hash.to_owned(),
+            None => cache.full_hash(file.path(), hash_type),
+        };
+        let mushlink = MushLink {
+            action: MushAction::Remove,
+            hash: hash.to_owned(),
+            src: PathBuf::new(),
+            dst: file.path().to_path_buf(),
+            dst_hash: None,
+            meta: FileMeta::of(file.path()),
+            duplicate_count: None,
+        };
+        removals.push((format!("{}[r{}]", hash, removals.len() + 1), mushlink));
+    }
+    removals
+}
+
+/// Walk every file beneath root, skipping mush's own state directory
+fn walk_files(root: &str) -> impl Iterator<Item = walkdir::DirEntry> {
+    WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.file_name() != MUSH_DIR)
+        .map(|e| e.expect("Expected file"))
+        .filter(|e| e.path().is_file())
+}
+
+/// Seed mushmap with the files already present in dst
+fn index_dst(
+    dst: &str,
+    mushmap: &mut HashMap<String, MushLink>,
+    keys: Option<&HashMap<PathBuf, String>>,
+    cache: &Cache,
+    hash_type: HashType,
+) {
+    if !Path::new(dst).is_dir() {
+        return;
+    }
+
+    info!("Indexing destination {}...", dst);
+    for file in walk_files(dst) {
+        let path = file.path().to_path_buf();
+        let hash = match keys.map(|keys| keys.get(&path)) {
+            Some(Some(hash)) => hash.to_owned(),
+            //Left unhashed as no source file is like it
+            Some(None) => continue,
+            None => cache.full_hash(&path, hash_type),
+        };
+        mushmap.entry(hash.to_owned()).or_insert(MushLink {
+            action: MushAction::Skip,
+            hash,
+            src: path.to_owned(),
+            dst: path,
+            dst_hash: None,
+            meta: None,
+            duplicate_count: Some(0),
+        });
+    }
 }
 
-#[allow(dead_code)]
-enum HashType {
+/// Algorithm used to hash file contents
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HashType {
+    /// Fast, not collision resistant against crafted files
+    #[default]
     Seahash,
+    /// Cryptographic and fast
+    Blake3,
+    /// Cryptographic, for when a standard digest must be cited
+    Sha256,
+    /// Fastest, not collision resistant against crafted files
+    Xxh3,
+}
+
+impl HashType {
+    /// Algorithm named in a manifest header
+    pub fn from_name(name: &str) -> Option<HashType> {
+        HashType::value_variants()
+            .iter()
+            .find(|hash_type| hash_type.to_string() == name)
+            .copied()
+    }
 }
 
-fn get_file_hash(path: &std::path::PathBuf, hash_type: Option<HashType>) -> String {
+impl std::fmt::Display for HashType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HashType::Seahash => "seahash",
+            HashType::Blake3 => "blake3",
+            HashType::Sha256 => "sha256",
+            HashType::Xxh3 => "xxh3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Bytes read from a file at a time while hashing it
+const HASH_BUFFER_LEN: usize = 64 * 1024;
+
+fn get_file_hash(path: &Path, hash_type: Option<HashType>) -> String {
     let input = std::fs::File::open(path).expect("Expected to open file");
-    let reader = std::io::BufReader::new(input);
+    hash_reader(
+        std::io::BufReader::new(input),
+        hash_type.unwrap_or_default(),
+    )
+}
+
+fn hash_reader<R: Read>(reader: R, hash_type: HashType) -> String {
     match hash_type {
-        Some(HashType::Seahash) => get_seahash(reader).to_string(),
-        None => get_seahash(reader).to_string(),
+        HashType::Seahash => get_seahash(reader).to_string(),
+        HashType::Blake3 => get_blake3(reader).to_string(),
+        HashType::Sha256 => get_sha256(reader),
+        HashType::Xxh3 => format!("{:016x}", get_xxh3(reader)),
     }
 }
 
 fn get_seahash<R: Read>(mut reader: R) -> u64 {
     let mut hasher = seahash::SeaHasher::default();
-    let mut buffer = [0; 1024];
+    let mut buffer = vec![0; HASH_BUFFER_LEN];
     loop {
         let count = reader
             .read(&mut buffer)
@@ -293,6 +837,49 @@ fn get_seahash<R: Read>(mut reader: R) -> u64 {
     hasher.finish()
 }
 
+fn get_blake3<R: Read>(mut reader: R) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_reader(&mut reader)
+        .expect("Expected to read from reader");
+    hasher.finalize()
+}
+
+fn get_sha256<R: Read>(mut reader: R) -> String {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = vec![0; HASH_BUFFER_LEN];
+    loop {
+        let count = reader
+            .read(&mut buffer)
+            .expect("Expected to read from reader");
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn get_xxh3<R: Read>(mut reader: R) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    let mut buffer = vec![0; HASH_BUFFER_LEN];
+    loop {
+        let count = reader
+            .read(&mut buffer)
+            .expect("Expected to read from reader");
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+    hasher.digest()
+}
+
 fn compare_files(file1: &PathBuf, file2: &PathBuf) -> bool {
     let mut f1 = File::open(file1).expect("Expected to open file1");
     let mut f2 = File::open(file2).expect("Expected to open file2");
@@ -300,22 +887,6 @@ fn compare_files(file1: &PathBuf, file2: &PathBuf) -> bool {
     let mut buffer1 = [0; 4096];
     let mut buffer2 = [0; 4096];
 
-    let total_bytes = f1.metadata().unwrap().len();
-    let mut bytes_processed = 0;
-    // print!(
-    //     "{}",
-    //     style!(
-    //         "dim,white",
-    //         "Comparing {:?} and {:?}",
-    //         file1.file_name().unwrap(),
-    //         file2.file_name().unwrap()
-    //     )
-    // );
-    // println!(
-    //     "{}",
-    //     style!("dim,white", "{} bytes to compare", total_bytes)
-    // );
-
     loop {
         let bytes_read1 = f1.read(&mut buffer1).expect("Expected to read from file1");
         let bytes_read2 = f2.read(&mut buffer2).expect("Expected to read from file2");
@@ -339,112 +910,154 @@ fn compare_files(file1: &PathBuf, file2: &PathBuf) -> bool {
         if buffer1[..bytes_read1] != buffer2[..bytes_read2] {
             return false; // Bytes differ
         }
-
-        bytes_processed += bytes_read1 as u64;
-        // let percent = (bytes_processed * 100) / total_bytes;
-        //print!("\rComparing {}%", percent);
     }
     true // Files are identical
 }
 
-#[allow(dead_code, unused_variables)]
-pub fn push(manifest: &Manifest, mode: &MushMode) {
-    match manifest {
-        Manifest::File(ref file) => {
-            let reader = BufReader::new(file);
-            let lines = reader.lines();
-            for line in lines {
-                let line = line.unwrap();
-                let mut fields = line.split(',');
-                let action = fields.next().expect("Line missing action");
-                let hash = fields.next().expect("Line missing hash");
-                let src = fields.next().expect("Line missing src");
-                let dst = fields.next().expect("Line missing dst");
-
-                let mushlink = MushLink {
-                    action: MushAction::from_string(action).unwrap(),
-                    hash: hash.to_owned(),
-                    src: src.to_owned(),
-                    dst: dst.to_owned(),
-                    duplicate_count: None,
-                };
-
-                println!("{}: {}", hash, mushlink);
-            }
+/// When an Update entry should replace the file at its destination
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum UpdatePolicy {
+    /// Always replace the destination file
+    Always,
+    /// Replace the destination file only if the source was modified more recently
+    Newer,
+    /// Never replace an existing destination file
+    Never,
+}
+
+/// Options controlling how `push` applies a manifest
+pub struct PushOptions {
+    pub mode: MushMode,
+    pub update: UpdatePolicy,
+    /// Move removed files here instead of deleting them
+    pub trash: Option<PathBuf>,
+}
+
+/// Outcome of applying a single manifest entry
+#[derive(Debug)]
+pub enum MushOutcome {
+    /// File was copied to its destination
+    Copied,
+    /// File was moved to its destination
+    Moved,
+    /// File was deleted from its destination
+    Removed,
+    /// File was moved from its destination into the trash folder
+    Trashed,
+    /// Entry required no file operation
+    Skipped,
+    /// File operation failed
+    Failed(MushActionError),
+}
+
+/// Apply every entry in the manifest and return the outcome of each one
+pub fn push(
+    manifest: &Manifest,
+    options: &PushOptions,
+) -> Result<Vec<(MushLink, MushOutcome)>, MushActionError> {
+    Ok(manifest_links(manifest)?
+        .into_iter()
+        .map(|link| {
+            let result = match link.action {
+                MushAction::Add | MushAction::Collision => {
+                    transfer(&link.src, &link.dst, &options.mode)
+                }
+                MushAction::Update => update(&link, options),
+                //Ignore entries may come from a manifest edited after scanning
+                MushAction::Ignore => Ok(MushOutcome::Skipped),
+                MushAction::Retreive => transfer(&link.dst, &link.src, &options.mode),
+                MushAction::Remove => remove(link.target(), &options.trash),
+                _ => Ok(MushOutcome::Skipped),
+            };
+            let outcome = match result {
+                Ok(outcome) => outcome,
+                Err(e) => MushOutcome::Failed(e),
+            };
+            (link, outcome)
+        })
+        .collect())
+}
+
+/// Replace the file at dst according to the update policy
+fn update(link: &MushLink, options: &PushOptions) -> Result<MushOutcome, MushActionError> {
+    let replace = match options.update {
+        UpdatePolicy::Always => true,
+        UpdatePolicy::Never => false,
+        UpdatePolicy::Newer => {
+            let modified = |path: &Path| {
+                std::fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .map_err(|e| MushActionError {
+                        message: format!(
+                            "Failed to read modified time of {}: {}",
+                            path.display(),
+                            e
+                        ),
+                    })
+            };
+            modified(&link.src)? > modified(&link.dst)?
         }
-        Manifest::Map(ref map) => {
-            for (hash, mushlink) in map {
-                println!("{}: {}", hash, mushlink);
-            }
+    };
+
+    match replace {
+        true => transfer(&link.src, &link.dst, &options.mode),
+        false => Ok(MushOutcome::Skipped),
+    }
+}
+
+/// Delete dst, or move it into trash keeping its full path beneath the trash folder
+fn remove(dst: &Path, trash: &Option<PathBuf>) -> Result<MushOutcome, MushActionError> {
+    match trash {
+        Some(trash) => {
+            let relative: PathBuf = dst
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect();
+            transfer(dst, &trash.join(relative), &MushMode::Move)?;
+            Ok(MushOutcome::Trashed)
+        }
+        None => {
+            std::fs::remove_file(dst).map_err(|e| MushActionError {
+                message: format!("Failed to remove {}: {}", dst.display(), e),
+            })?;
+            Ok(MushOutcome::Removed)
         }
     }
+}
+
+/// Copy or move src to dst, creating any missing parent directories
+fn transfer(src: &Path, dst: &Path, mode: &MushMode) -> Result<MushOutcome, MushActionError> {
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| MushActionError {
+            message: format!("Failed to create directory {}: {}", parent.display(), e),
+        })?;
+    }
 
-    // let reader = BufReader::new(manifest_file);
-    // let lines = reader.lines();
-
-    // for line in lines {
-    //     let line = line.unwrap();
-    //     let mut fields = line.split(',');
-    //     let action = fields.next().expect("Line missing action");
-    //     let hash = fields.next().expect("Line missing hash");
-    //     let src = fields.next().expect("Line missing src");
-    //     let dst = fields.next().expect("Line missing dst");
-
-    //     match MushAction::from_string(action) {
-    //         Some(MushAction::Add) => {
-    //             let src_file = PathBuf::from(src);
-    //             let dst_file = PathBuf::from(dst);
-    //             let dst_path = dst_file.parent().unwrap();
-    //             if let Err(e) = std::fs::create_dir_all(dst_path) {
-    //                 eprintln!(
-    //                     "{}: Failed to create directory: {}",
-    //                     style!("red", "ERROR"),
-    //                     e
-    //                 );
-    //                 continue;
-    //             }
-    //             match mode {
-    //                 MushMode::Copy => {
-    //                     println!(
-    //                         "{}",
-    //                         style!(
-    //                             "cyan",
-    //                             "Copying {} to {}",
-    //                             src_file.display(),
-    //                             dst_file.display()
-    //                         )
-    //                     );
-    //                     if let Err(e) = std::fs::copy(&src_file, &dst_file) {
-    //                         eprintln!("{}: Failed to copy file: {}", style!("red", "ERROR"), e);
-    //                         continue;
-    //                     }
-    //                     println!("{}", style!("green", "Copied successfully"));
-    //                 }
-    //                 MushMode::Move => {
-    //                     println!(
-    //                         "{}",
-    //                         style!(
-    //                             "cyan",
-    //                             "Moving {} to {}",
-    //                             src_file.display(),
-    //                             dst_file.display()
-    //                         )
-    //                     );
-    //                     if let Err(e) = std::fs::rename(&src_file, &dst_file) {
-    //                         eprintln!("{}: Failed to move file: {}", style!("red", "ERROR"), e);
-    //                         continue;
-    //                     }
-    //                     println!("{}", style!("green", "Moved successfully"));
-    //                 }
-    //             }
-    //         }
-    //         Some(MushAction::Skip) => {
-    //             println!(
-    //                 "{}",
-    //                 style!("dim, white", "Skipping {} as is duplicate of {}", src, dst)
-    //             );
-    //         }
-    //         _ => (),
-    //     }
-    // }
+    let copy = || {
+        std::fs::copy(src, dst).map_err(|e| MushActionError {
+            message: format!(
+                "Failed to copy {} to {}: {}",
+                src.display(),
+                dst.display(),
+                e
+            ),
+        })
+    };
+
+    match mode {
+        MushMode::Copy => {
+            copy()?;
+            Ok(MushOutcome::Copied)
+        }
+        MushMode::Move => {
+            //Rename fails across filesystems so fall back to copy and remove
+            if std::fs::rename(src, dst).is_err() {
+                copy()?;
+                std::fs::remove_file(src).map_err(|e| MushActionError {
+                    message: format!("Copied {} but failed to remove it: {}", src.display(), e),
+                })?;
+            }
+            Ok(MushOutcome::Moved)
+        }
+    }
 }
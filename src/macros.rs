@@ -65,7 +65,6 @@ macro_rules! style {
 }
 
 /** Colour macros */
-
 #[macro_export]
 macro_rules! black {
     ($($arg:tt)*) => {
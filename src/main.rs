@@ -1,9 +1,14 @@
 use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand};
 
-use mush::{MushLink, MushMode};
-use mush::{scan, push};
+use mush::{CollisionPolicy, HashCache, HashType, MushAction, MushActionError, MushLink, MushMode, MushOutcome};
+use mush::{PathConflictPolicy, PushOptions, ScanOptions, UpdatePolicy};
+use mush::{Query, QueryOutput};
+use mush::{check_signature, generate_key, seal};
+use mush::{diff, export, merge, plan, query, render, stats, push, read_manifest, rebase, save_sync_state, scan, sync, validate, Manifest, ManifestFormat};
 
 mod macros;
 
@@ -27,13 +32,46 @@ enum Commands {
         #[arg(short, long, value_name = "PATH", required = true)]
         dst: String,
         #[arg(short, long, value_name = "MANIFEST_FILE", default_value = "manifest.mush")]
-        manifest: String
+        manifest: String,
+        /// Manifest file format, guessed from the manifest file extension when omitted
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<ManifestFormat>,
+        /// Skip source files whose content already exists anywhere in the destination
+        #[arg(long)]
+        index_dst: bool,
+        /// Remove files from the destination that no source file maps to
+        #[arg(long)]
+        mirror: bool,
+        /// How to resolve files whose hash matches a file with different content
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        collision: CollisionPolicy,
+        /// How to resolve different files from several sources that map to the same destination path
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        path_conflict: PathConflictPolicy,
+        /// Algorithm used to hash file contents
+        #[arg(long, value_name = "ALGORITHM", default_value = "seahash")]
+        hash: HashType,
+        /// Hash the whole content of every source file, not only the ends of files nothing else could duplicate
+        #[arg(long)]
+        full_digests: bool,
+        /// Threads walking and hashing files, 0 for one per core
+        #[arg(long, value_name = "COUNT", default_value_t = 0)]
+        threads: usize,
+        /// Where to keep hashes of unchanged files between scans
+        #[arg(long, value_name = "STORE", default_value = "off")]
+        hash_cache: HashCache,
+        /// Reuse hashes from the manifest of the last scan for files unchanged since
+        #[arg(long)]
+        since_last: bool,
     },
     /// Perform file mush
     Run {
         /// From provided manifest
         #[arg(short, long, value_name = "MANIFEST_FILE")]
         manifest: Option<String>,
+        /// Manifest file format, guessed from the manifest file extension when omitted
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<ManifestFormat>,
         /// From one or more source directories
         #[arg(short, long, value_name = "PATH", num_args = 1..,value_delimiter = ' ')]
         src: Option<Vec<String>>,
@@ -43,6 +81,48 @@ enum Commands {
         /// Move or Copy to the destination
         #[arg(long)]
         mode: MushMode,
+        /// When to replace changed files that already exist in the destination
+        #[arg(long, value_name = "POLICY", default_value = "newer")]
+        update: UpdatePolicy,
+        /// Skip source files whose content already exists anywhere in the destination
+        #[arg(long)]
+        index_dst: bool,
+        /// Remove files from the destination that no source file maps to
+        #[arg(long)]
+        mirror: bool,
+        /// Move removed files into this folder instead of deleting them, keep it outside the destination
+        #[arg(long, value_name = "PATH")]
+        trash: Option<String>,
+        /// How to resolve files whose hash matches a file with different content
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        collision: CollisionPolicy,
+        /// How to resolve different files from several sources that map to the same destination path
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        path_conflict: PathConflictPolicy,
+        /// Algorithm used to hash file contents
+        #[arg(long, value_name = "ALGORITHM", default_value = "seahash")]
+        hash: HashType,
+        /// Hash the whole content of every source file, not only the ends of files nothing else could duplicate
+        #[arg(long)]
+        full_digests: bool,
+        /// Threads walking and hashing files, 0 for one per core
+        #[arg(long, value_name = "COUNT", default_value_t = 0)]
+        threads: usize,
+        /// Where to keep hashes of unchanged files between scans
+        #[arg(long, value_name = "STORE", default_value = "off")]
+        hash_cache: HashCache,
+        /// Print the plan without touching any files, exits 0 when there is nothing to do and 2 when changes are pending
+        #[arg(long)]
+        dry_run: bool,
+        /// Move a source root of the manifest, as NAME=PATH or just PATH when it has one source
+        #[arg(long, value_name = "[NAME=]PATH", requires = "manifest")]
+        rebase_src: Vec<String>,
+        /// Move the destination root of the manifest
+        #[arg(long, value_name = "PATH", requires = "manifest")]
+        rebase_dst: Option<String>,
+        /// Refuse the manifest unless it is signed by this public key file
+        #[arg(long, value_name = "KEY_FILE", requires = "manifest")]
+        trusted_key: Option<String>,
     },
     /// Push from current directory to a destination directory
     Push {
@@ -52,21 +132,226 @@ enum Commands {
         /// Move or Copy to the destination
         #[arg(long)]
         mode: MushMode,
+        /// When to replace changed files that already exist in the destination
+        #[arg(long, value_name = "POLICY", default_value = "newer")]
+        update: UpdatePolicy,
+        /// Skip source files whose content already exists anywhere in the destination
+        #[arg(long)]
+        index_dst: bool,
+        /// How to resolve files whose hash matches a file with different content
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        collision: CollisionPolicy,
+        /// Print the plan without touching any files, exits 0 when there is nothing to do and 2 when changes are pending
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Two-way sync between a source and destination directory
+    Sync {
+        /// Source directory
+        #[arg(short, long, value_name = "PATH")]
+        src: String,
+        /// Destination directory
+        #[arg(short, long, value_name = "PATH")]
+        dst: String,
+        /// Move removed files into this folder instead of deleting them, keep it outside both sides
+        #[arg(long, value_name = "PATH")]
+        trash: Option<String>,
+    },
+    /// Check a manifest against the files it names without changing anything, exits 1 when it is stale or invalid
+    Validate {
+        #[arg(short, long, value_name = "MANIFEST_FILE", required = true)]
+        manifest: String,
+        /// Manifest file format, guessed from the manifest file extension when omitted
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<ManifestFormat>,
+    },
+    /// Compare or combine manifest files
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommands,
     },
     /// Pull files from one or more source directories to current directory
     Pull {
         /// One or more source directories
-        #[arg(short, long, value_name = "PATH", num_args = 1..,value_delimiter = ' ')]
+        #[arg(short, long, value_name = "PATH", num_args = 1..,value_delimiter = ' ', required = true)]
         src: Vec<String>,
-        /// Destination folder
+        /// Destination folder, defaults to the current directory
         #[arg(short, long, value_name = "PATH")]
         dst: Option<String>,
         /// Move or Copy to the destination
         #[arg(long)]
         mode: MushMode,
+        /// When to replace changed files that already exist in the destination
+        #[arg(long, value_name = "POLICY", default_value = "newer")]
+        update: UpdatePolicy,
+        /// How to resolve files whose hash matches a file with different content
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        collision: CollisionPolicy,
+        /// How to resolve different files from several sources that map to the same destination path
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        path_conflict: PathConflictPolicy,
+        /// Algorithm used to hash file contents
+        #[arg(long, value_name = "ALGORITHM", default_value = "seahash")]
+        hash: HashType,
+        /// Threads walking and hashing files, 0 for one per core
+        #[arg(long, value_name = "COUNT", default_value_t = 0)]
+        threads: usize,
+        /// Where to keep hashes of unchanged files between scans
+        #[arg(long, value_name = "STORE", default_value = "off")]
+        hash_cache: HashCache,
+        /// Print the plan without touching any files, exits 0 when there is nothing to do and 2 when changes are pending
+        #[arg(long)]
+        dry_run: bool,
+    }
+}
+
+#[derive(Subcommand)]
+enum ManifestCommands {
+    /// Show entries whose action, hash or destination changed between two manifests, exits 1 when they differ and 2 when one cannot be read
+    Diff {
+        #[arg(value_name = "MANIFEST_FILE")]
+        a: String,
+        #[arg(value_name = "MANIFEST_FILE")]
+        b: String,
+    },
+    /// Combine manifests into one plan, finding duplicates and conflicts across them the way scan does
+    Merge {
+        #[arg(value_name = "MANIFEST_FILE", num_args = 2.., required = true)]
+        inputs: Vec<String>,
+        /// Merged manifest to write
+        #[arg(short, long, value_name = "MANIFEST_FILE", default_value = "manifest.mush")]
+        manifest: String,
+        /// Manifest file format, guessed from the manifest file extension when omitted
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<ManifestFormat>,
+        /// Destination to merge into, defaults to the one the manifests share
+        #[arg(short, long, value_name = "PATH")]
+        dst: Option<String>,
+        /// Skip source files whose content already exists anywhere in the destination
+        #[arg(long)]
+        index_dst: bool,
+        /// Remove files from the destination that no source file maps to
+        #[arg(long)]
+        mirror: bool,
+        /// How to resolve files whose hash matches a file with different content
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        collision: CollisionPolicy,
+        /// How to resolve different files from several sources that map to the same destination path
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        path_conflict: PathConflictPolicy,
+        /// Algorithm used to hash file contents
+        #[arg(long, value_name = "ALGORITHM", default_value = "seahash")]
+        hash: HashType,
+        /// Hash the whole content of every source file, not only the ends of files nothing else could duplicate
+        #[arg(long)]
+        full_digests: bool,
+    },
+    /// Write a new trailer for a manifest edited by hand, signing it when given a secret key file
+    Seal {
+        #[arg(value_name = "MANIFEST_FILE")]
+        manifest: String,
+        /// Secret key file to sign the manifest with
+        #[arg(long, value_name = "KEY_FILE")]
+        key: Option<String>,
+    },
+    /// Create a key pair for signing manifests, the public key is written beside it with a .pub extension
+    Keygen {
+        #[arg(value_name = "KEY_FILE")]
+        key: String,
+    },
+    /// Write a POSIX shell script that applies the manifest, for review before running it
+    Export {
+        #[arg(value_name = "MANIFEST_FILE")]
+        manifest: String,
+        /// Script file to write, printed when omitted
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+        /// Whether the script copies or moves source files
+        #[arg(long, value_name = "MODE", default_value = "copy")]
+        mode: MushMode,
+    },
+    /// Report totals by action, bytes to copy, bytes saved by dedup and the largest duplicate groups
+    Stats {
+        #[arg(value_name = "MANIFEST_FILE")]
+        manifest: String,
+        /// Number of duplicate groups to list
+        #[arg(long, value_name = "COUNT", default_value_t = 10)]
+        top: usize,
+    },
+    /// List the entries that pass every filter given
+    Query {
+        #[arg(value_name = "MANIFEST_FILE")]
+        manifest: String,
+        /// Keep entries with this action, may be repeated
+        #[arg(long, value_name = "ACTION")]
+        action: Vec<MushAction>,
+        /// Keep entries whose src or dst matches this glob pattern
+        #[arg(long, value_name = "PATTERN")]
+        glob: Option<String>,
+        /// Keep entries whose hash starts with this prefix
+        #[arg(long, value_name = "PREFIX")]
+        hash: Option<String>,
+        /// Keep entries of at least this many bytes
+        #[arg(long, value_name = "BYTES")]
+        min_size: Option<u64>,
+        /// Keep entries of at most this many bytes
+        #[arg(long, value_name = "BYTES")]
+        max_size: Option<u64>,
+        /// How to print the entries
+        #[arg(long, value_name = "OUTPUT", default_value = "table")]
+        output: QueryOutput,
+    },
+}
+
+/// Open a manifest file in the format its extension implies
+fn open_manifest(path: &str) -> Manifest {
+    let file = std::fs::File::open(path).expect("Could not open manifest file");
+    Manifest::File(file, ManifestFormat::from_path(Path::new(path)))
+}
+
+fn report(outcomes: &[(MushLink, MushOutcome)]) {
+    for (link, outcome) in outcomes {
+        match outcome {
+            MushOutcome::Copied if link.action == MushAction::Retreive => {
+                success!("Retrieved {} to {}", link.dst.display(), link.src.display())
+            }
+            MushOutcome::Copied => success!("Copied {} to {}", link.src.display(), link.dst.display()),
+            MushOutcome::Moved => success!("Moved {} to {}", link.src.display(), link.dst.display()),
+            MushOutcome::Removed | MushOutcome::Trashed => {
+                let path = link.target();
+                match outcome {
+                    MushOutcome::Removed => success!("Removed {}", path.display()),
+                    _ => success!("Trashed {}", path.display()),
+                }
+            }
+            MushOutcome::Skipped if link.action == MushAction::Conflict => {
+                warning!("Conflict: {} and {} both changed", link.src.display(), link.dst.display())
+            }
+            MushOutcome::Skipped => debug!("Skipped {} {}", link.action, link.src.display()),
+            MushOutcome::Failed(e) => failure!("{}", e),
+        }
     }
 }
 
+/// Report why mush cannot carry on and exit
+fn halt(e: MushActionError) -> ! {
+    failure!("{}", e);
+    std::process::exit(1);
+}
+
+/// Report a failure as diff(1) does, keeping exit code 1 for differences
+fn trouble(e: MushActionError) -> ! {
+    failure!("{}", e);
+    std::process::exit(2);
+}
+
+/// Print the plan for the manifest and exit with whether changes are pending
+fn preview(manifest: &Manifest) -> ! {
+    let plan = plan(manifest).unwrap_or_else(|e| halt(e));
+    print!("{}", plan);
+    std::process::exit(if plan.has_changes() { 2 } else { 0 });
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -81,25 +366,61 @@ fn main() {
     msg!("msg test");
 
     match cli.command {
-        Some(Commands::Scan { src, dst, manifest }) => {
+        Some(Commands::Scan { src, dst, manifest, format, index_dst, mirror, collision, path_conflict, hash, full_digests, threads, hash_cache, since_last }) => {
+            let format = format.unwrap_or_else(|| ManifestFormat::from_path(Path::new(&manifest)));
+            //The last manifest is read before the new one is written over it
+            let previous = match (since_last, std::fs::File::open(&manifest)) {
+                (false, _) => None,
+                (true, Ok(file)) => Some(read_manifest(&file, format).unwrap_or_else(|e| halt(e))),
+                (true, Err(_)) => {
+                    warning!("No manifest at {} to scan since, scanning every file", manifest);
+                    None
+                }
+            };
             let file = std::fs::File::create(manifest).expect("Could not create manifest file");
-            let mut manifest = mush::Manifest::File(file);
-            scan(src, dst, &mut manifest);
+            let mut manifest = mush::Manifest::File(file, format);
+            let options = ScanOptions { index_dst, mirror, collision, path_conflict, hash, full_digests, threads, hash_cache, previous };
+            if let Err(e) = scan(src, dst, &mut manifest, &options) {
+                halt(e);
+            }
         }
-        Some(Commands::Run { manifest, src, dst, mode }) => {
+        Some(Commands::Run { manifest, format, src, dst, mode, update, index_dst, mirror, trash, collision, path_conflict, hash, full_digests, threads, hash_cache, dry_run, rebase_src, rebase_dst, trusted_key }) => {
+            let trash = trash.map(PathBuf::from);
+            let push_options = PushOptions { mode, update, trash };
             match manifest {
                 Some(manifest) => {
+                    let format = format.unwrap_or_else(|| ManifestFormat::from_path(Path::new(&manifest)));
+                    if let Some(key) = trusted_key {
+                        check_signature(Path::new(&manifest), format, Path::new(&key)).unwrap_or_else(|e| halt(e));
+                    }
                     let file = std::fs::File::open(manifest).expect("Could not open manifest file");
-                    let manifest = mush::Manifest::File(file);
-                    push(&manifest, &mode);
+                    let manifest = match rebase_src.is_empty() && rebase_dst.is_none() {
+                        true => mush::Manifest::File(file, format),
+                        false => {
+                            let (header, links) = read_manifest(&file, format).unwrap_or_else(|e| halt(e));
+                            let (_, links) = rebase(&header, links, &rebase_src, rebase_dst.as_deref()).unwrap_or_else(|e| halt(e));
+                            mush::Manifest::Map(links.into_iter().enumerate().map(|(i, link)| (i.to_string(), link)).collect())
+                        }
+                    };
+                    if dry_run {
+                        preview(&manifest);
+                    }
+                    report(&push(&manifest, &push_options).unwrap_or_else(|e| halt(e)));
                 },
                 None => {
                     if src.is_none() || dst.is_none() {
                         panic!("Must provide both src and dst to run without manifest");
                     }
                     let mut manifest = mush::Manifest::Map(HashMap::<String, MushLink>::new());
-                    let manifest = scan(src.unwrap(), dst.unwrap(), &mut manifest);
-                    push(&manifest, &mode);
+                    //A dry run leaves the filesystem as it found it, cache included
+                    let hash_cache = if dry_run { HashCache::Off } else { hash_cache };
+                    let options = ScanOptions { index_dst, mirror, collision, path_conflict, hash, full_digests, threads, hash_cache, previous: None };
+                    let manifest = scan(src.unwrap(), dst.unwrap(), &mut manifest, &options)
+                        .unwrap_or_else(|e| halt(e));
+                    if dry_run {
+                        preview(manifest);
+                    }
+                    report(&push(manifest, &push_options).unwrap_or_else(|e| halt(e)));
                 }
             }
             // if let Some(manifest) = manifest {
@@ -108,14 +429,92 @@ fn main() {
             //     panic!("No manifest provided");
             // }
         }
-        Some(Commands::Push { dst, mode }) => {
+        Some(Commands::Push { dst, mode, update, index_dst, collision, dry_run }) => {
+            let push_options = PushOptions { mode, update, trash: None };
             let src = vec![std::env::current_dir().unwrap().to_str().unwrap().to_string()];
             let mut manifest = mush::Manifest::Map(HashMap::<String, MushLink>::new());
-            scan(src, dst, &mut manifest);
-            push(&manifest, &mode);
+            let options = ScanOptions { index_dst, collision, ..Default::default() };
+            if let Err(e) = scan(src, dst, &mut manifest, &options) {
+                halt(e);
+            }
+            if dry_run {
+                preview(&manifest);
+            }
+            report(&push(&manifest, &push_options).unwrap_or_else(|e| halt(e)));
         },
-        Some(Commands::Pull { src, dst, mode }) => {
-            todo!("Pull not implemented yet");
+        Some(Commands::Pull { src, dst, mode, update, collision, path_conflict, hash, threads, hash_cache, dry_run }) => {
+            let push_options = PushOptions { mode, update, trash: None };
+            let dst = dst.unwrap_or(std::env::current_dir().unwrap().to_str().unwrap().to_string());
+            let mut manifest = mush::Manifest::Map(HashMap::<String, MushLink>::new());
+            let hash_cache = if dry_run { HashCache::Off } else { hash_cache };
+            let options = ScanOptions { index_dst: true, collision, path_conflict, hash, threads, hash_cache, ..Default::default() };
+            if let Err(e) = scan(src, dst, &mut manifest, &options) {
+                halt(e);
+            }
+            if dry_run {
+                preview(&manifest);
+            }
+            report(&push(&manifest, &push_options).unwrap_or_else(|e| halt(e)));
+        }
+        Some(Commands::Sync { src, dst, trash }) => {
+            let trash = trash.map(PathBuf::from);
+            let push_options = PushOptions { mode: MushMode::Copy, update: UpdatePolicy::Always, trash };
+            let mut manifest = mush::Manifest::Map(HashMap::<String, MushLink>::new());
+            sync(&src, &dst, &mut manifest);
+            let outcomes = push(&manifest, &push_options).unwrap_or_else(|e| halt(e));
+            report(&outcomes);
+            save_sync_state(&src, &dst, &outcomes);
+        }
+        Some(Commands::Validate { manifest, format }) => {
+            let format = format.unwrap_or_else(|| ManifestFormat::from_path(Path::new(&manifest)));
+            let file = std::fs::File::open(manifest).expect("Could not open manifest file");
+            let validation = validate(&mush::Manifest::File(file, format)).unwrap_or_else(|e| halt(e));
+            print!("{}", validation);
+            std::process::exit(if validation.passed() { 0 } else { 1 });
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Diff { a, b } }) => {
+            let open = |path: &str| std::fs::File::open(path).map(|file| Manifest::File(file, ManifestFormat::from_path(Path::new(path)))).map_err(|e| MushActionError { message: format!("Failed to open {}: {}", path, e) });
+            let diff = open(&a).and_then(|a| open(&b).and_then(|b| diff(&a, &b))).unwrap_or_else(|e| trouble(e));
+            print!("{}", diff);
+            std::process::exit(if diff.is_empty() { 0 } else { 1 });
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Merge { inputs, manifest, format, dst, index_dst, mirror, collision, path_conflict, hash, full_digests } }) => {
+            let inputs = inputs.iter().map(|path| {
+                let format = ManifestFormat::from_path(Path::new(path));
+                let file = std::fs::File::open(path).expect("Could not open manifest file");
+                read_manifest(&file, format).unwrap_or_else(|e| halt(e))
+            }).collect();
+            let format = format.unwrap_or_else(|| ManifestFormat::from_path(Path::new(&manifest)));
+            let file = std::fs::File::create(manifest).expect("Could not create manifest file");
+            let mut manifest = mush::Manifest::File(file, format);
+            let options = ScanOptions { index_dst, mirror, collision, path_conflict, hash, full_digests, ..Default::default() };
+            if let Err(e) = merge(inputs, dst, &mut manifest, &options) {
+                halt(e);
+            }
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Seal { manifest, key } }) => {
+            let format = ManifestFormat::from_path(Path::new(&manifest));
+            let entries = seal(Path::new(&manifest), format, key.as_deref().map(Path::new)).unwrap_or_else(|e| halt(e));
+            success!("Sealed {} entries in {}", entries, manifest);
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Keygen { key } }) => {
+            let public = generate_key(Path::new(&key)).unwrap_or_else(|e| halt(e));
+            success!("Wrote secret key {} and public key {}", key, public.display());
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Export { manifest, output, mode } }) => {
+            let script = export(&open_manifest(&manifest), mode).unwrap_or_else(|e| halt(e));
+            match output {
+                Some(output) => std::fs::write(output, script).expect("Could not write script file"),
+                None => std::io::stdout().write_all(&script).expect("Could not print script"),
+            }
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Stats { manifest, top } }) => {
+            print!("{}", stats(&open_manifest(&manifest), top).unwrap_or_else(|e| halt(e)));
+        }
+        Some(Commands::Manifest { command: ManifestCommands::Query { manifest, action, glob, hash, min_size, max_size, output } }) => {
+            let filters = Query { actions: action, glob, hash, min_size, max_size };
+            let links = query(&open_manifest(&manifest), &filters).unwrap_or_else(|e| halt(e));
+            print!("{}", render(&links, output));
         }
         None => {}
     }
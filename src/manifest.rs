@@ -0,0 +1,495 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::format::{self, ManifestFormat};
+use crate::roots::{link_from_roots, link_to_roots};
+use crate::seal::{check_trailer, sealed, split_trailer};
+use crate::{FileMeta, HashType, MushAction, MushActionError, MushLink};
+
+/// Current manifest format version, manifests without a header are version 1.
+/// Every manifest with a header must end with a trailer, so versions 2 and 3,
+/// which had none, are refused.
+pub const MANIFEST_VERSION: u32 = 4;
+
+pub enum Manifest {
+    File(File, ManifestFormat),
+    Map(HashMap<String, MushLink>),
+}
+
+/// Describes how and from what a manifest file was built
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestHeader {
+    /// Manifest format version
+    pub version: u32,
+    /// Version of mush that wrote the manifest
+    pub mush_version: String,
+    /// Algorithm used for the hash column
+    pub hash: String,
+    /// Source roots that were scanned
+    pub src: Vec<String>,
+    /// Destination root
+    pub dst: String,
+    /// Seconds since the unix epoch when the scan started
+    pub scanned: u64,
+}
+
+impl ManifestHeader {
+    pub fn new(src: &[String], dst: &str) -> ManifestHeader {
+        ManifestHeader {
+            version: MANIFEST_VERSION,
+            mush_version: String::from(env!("CARGO_PKG_VERSION")),
+            hash: String::from("seahash"),
+            src: src.to_vec(),
+            dst: dst.to_owned(),
+            scanned: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Algorithm the hash column was written with
+    pub fn hash_type(&self) -> Result<HashType, MushActionError> {
+        HashType::from_name(&self.hash).ok_or_else(|| MushActionError {
+            message: format!("Manifest is hashed with unknown algorithm {}", self.hash),
+        })
+    }
+
+    /// Header assumed for manifests written before the header existed
+    fn legacy() -> ManifestHeader {
+        ManifestHeader {
+            version: 1,
+            mush_version: String::new(),
+            hash: String::from("seahash"),
+            src: Vec::new(),
+            dst: String::new(),
+            scanned: 0,
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, MushActionError> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, MushActionError> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| MushActionError {
+            message: format!("Manifest path is not valid UTF-8: {}", e),
+        })
+}
+
+/// Escape a path for a single manifest field.
+///
+/// `%`, `,`, control characters and bytes that are not valid UTF-8 are written
+/// as `%XX` so any path survives the round trip through `decode_path`.
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for chunk in path_bytes(path).utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '%' | ',' => write!(encoded, "%{:02X}", c as u8).unwrap(),
+                c if c.is_control() => {
+                    for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                        write!(encoded, "%{:02X}", byte).unwrap();
+                    }
+                }
+                c => encoded.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            write!(encoded, "%{:02X}", byte).unwrap();
+        }
+    }
+    encoded
+}
+
+/// Reverse `encode_path`
+pub fn decode_path(field: &str) -> Result<PathBuf, MushActionError> {
+    let invalid = || MushActionError {
+        message: format!("Manifest path has an invalid escape: {}", field),
+    };
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = rest.get(..2).ok_or_else(invalid)?;
+        let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+        bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        rest = &rest[2..];
+    }
+    path_from_bytes(bytes)
+}
+
+/// Decode a header root, which was given as a string when scanning
+fn decode_root(value: &str) -> Result<String, MushActionError> {
+    decode_path(value).map(|p| p.to_string_lossy().into_owned())
+}
+
+impl std::fmt::Display for ManifestHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "#mush-manifest {}", self.version)?;
+        writeln!(f, "#mush-version {}", self.mush_version)?;
+        writeln!(f, "#hash {}", self.hash)?;
+        for (index, src) in self.src.iter().enumerate() {
+            writeln!(f, "#src src{} {}", index, encode_path(Path::new(src)))?;
+        }
+        writeln!(f, "#dst {}", encode_path(Path::new(&self.dst)))?;
+        writeln!(f, "#scanned {}", self.scanned)
+    }
+}
+
+/// Entry columns: action,hash,src,dst,dst_hash,size,mtime,mode
+///
+/// Paths are escaped with `encode_path`.
+impl std::fmt::Display for MushLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},",
+            self.action,
+            self.hash,
+            encode_path(&self.src),
+            encode_path(&self.dst)
+        )?;
+        if let Some(dst_hash) = &self.dst_hash {
+            write!(f, "{}", dst_hash)?;
+        }
+        match &self.meta {
+            Some(meta) => write!(f, ",{},{},{:o}", meta.size, meta.mtime, meta.mode),
+            None => write!(f, ",,,"),
+        }
+    }
+}
+
+fn write_csv(
+    out: &mut Vec<u8>,
+    header: &ManifestHeader,
+    links: &[MushLink],
+) -> std::io::Result<()> {
+    write!(out, "{}", header)?;
+    for link in links {
+        writeln!(out, "{}", link)?;
+    }
+    Ok(())
+}
+
+/// Store the links in the manifest, under their key when it is a map, files
+/// end with a trailer sealing what was written
+pub(crate) fn write_manifest(
+    manifest: &mut Manifest,
+    header: &ManifestHeader,
+    links: Vec<(String, MushLink)>,
+) -> Result<(), MushActionError> {
+    match manifest {
+        Manifest::File(ref file, format) => {
+            let links: Vec<MushLink> = links
+                .iter()
+                .map(|(_, link)| link_to_roots(header, link))
+                .collect();
+            let mut body = Vec::new();
+            let written = match format {
+                ManifestFormat::Csv => write_csv(&mut body, header, &links),
+                _ => format::write(&mut body, *format, header, &links),
+            };
+            let mut file = file;
+            written
+                .map(|_| sealed(&body, links.len(), *format, None))
+                .and_then(|bytes| file.write_all(&bytes))
+                .map_err(|e| MushActionError {
+                    message: format!("Failed to write manifest: {}", e),
+                })
+        }
+        Manifest::Map(ref mut map) => {
+            map.extend(links);
+            Ok(())
+        }
+    }
+}
+
+/// Every link held by the manifest
+pub(crate) fn manifest_links(manifest: &Manifest) -> Result<Vec<MushLink>, MushActionError> {
+    match manifest {
+        Manifest::File(ref file, format) => read_manifest(file, *format).map(|(_, links)| links),
+        Manifest::Map(ref map) => Ok(map.values().cloned().collect()),
+    }
+}
+
+/// Refuse manifests written by a newer mush, or by one that wrote no trailer
+pub(crate) fn check_version(version: u32) -> Result<(), MushActionError> {
+    match version {
+        //Only a missing header means version 1, which is read without a trailer
+        0 | 1 => Err(MushActionError {
+            message: format!(
+                "Manifest header names format version {}, which had no header",
+                version
+            ),
+        }),
+        2 | 3 => Err(MushActionError {
+            message: format!(
+                "Manifest format version {} has no trailer and cannot be checked for truncation or tampering, scan again to write a version {} manifest",
+                version, MANIFEST_VERSION
+            ),
+        }),
+        version if version > MANIFEST_VERSION => Err(MushActionError {
+            message: format!(
+                "Manifest format version {} is newer than this mush supports ({}), upgrade mush to use it",
+                version, MANIFEST_VERSION
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Read a manifest file, migrating one without a header and refusing others it cannot check
+pub fn read_manifest(
+    file: &File,
+    format: ManifestFormat,
+) -> Result<(ManifestHeader, Vec<MushLink>), MushActionError> {
+    let (header, links, errors) = read_entries(file, format)?;
+    if !errors.is_empty() {
+        return Err(invalid_entries(errors));
+    }
+    Ok((header, links))
+}
+
+/// Header, every usable link and a description of every unusable entry
+pub(crate) type Entries = (ManifestHeader, Vec<MushLink>, Vec<String>);
+
+/// Read a manifest file, keeping the entries that could be used alongside
+/// the problems found in the others, a bad trailer is one of the problems
+pub(crate) fn read_entries(
+    file: &File,
+    format: ManifestFormat,
+) -> Result<Entries, MushActionError> {
+    let mut bytes = Vec::new();
+    BufReader::new(file)
+        .read_to_end(&mut bytes)
+        .map_err(|e| MushActionError {
+            message: format!("Failed to read manifest: {}", e),
+        })?;
+    let (body, trailer) = split_trailer(&bytes, format);
+    let (header, links, mut errors) = parse_entries(&body, format)?;
+    let entries = links.len() + errors.len();
+    if let Some(e) = check_trailer(&header, &body, trailer.as_deref(), format, entries) {
+        errors.push(format!("trailer: {}", e));
+    }
+    Ok((header, links, errors))
+}
+
+/// Read the entries of a manifest body, without its trailer
+pub(crate) fn parse_entries(
+    body: &[u8],
+    format: ManifestFormat,
+) -> Result<Entries, MushActionError> {
+    match format {
+        ManifestFormat::Csv => read_csv(body),
+        _ => format::read(body, format),
+    }
+}
+
+fn read_csv(body: &[u8]) -> Result<Entries, MushActionError> {
+    let reader = BufReader::new(body);
+    let mut header: Option<ManifestHeader> = None;
+    let mut links = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let number = index + 1;
+        let line = line.map_err(|e| MushActionError {
+            message: format!("Failed to read manifest: {}", e),
+        })?;
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(field) = line.strip_prefix('#') {
+            let (key, value) = field.split_once(' ').unwrap_or((field, ""));
+            if key == "mush-manifest" {
+                let version = value.parse().map_err(|_| MushActionError {
+                    message: format!(
+                        "Manifest line {} has an invalid format version: {}",
+                        number, value
+                    ),
+                })?;
+                check_version(version)?;
+                header = Some(ManifestHeader {
+                    version,
+                    ..ManifestHeader::legacy()
+                });
+                continue;
+            }
+            //Unknown header fields are left for newer minor additions
+            if let Some(header) = header.as_mut() {
+                match key {
+                    "mush-version" => header.mush_version = value.to_owned(),
+                    "hash" => header.hash = value.to_owned(),
+                    //Source roots are named by their position
+                    "src" => {
+                        let (_, path) = value.split_once(' ').unwrap_or(("", value));
+                        header.src.push(decode_root(path)?)
+                    }
+                    "dst" => header.dst = decode_root(value)?,
+                    "scanned" => header.scanned = value.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+            continue;
+        }
+
+        let header = header.get_or_insert_with(|| {
+            warning!("Migrating manifest without a header from format version 1");
+            ManifestHeader::legacy()
+        });
+
+        let link = parse_line(&line)
+            .and_then(|link| link_from_roots(header, link))
+            .and_then(|link| check_roots(header, &link).map(|_| link));
+        match link {
+            Ok(link) => links.push(link),
+            Err(e) => errors.push(format!("line {}: {}", number, e)),
+        }
+    }
+
+    Ok((header.unwrap_or_else(ManifestHeader::legacy), links, errors))
+}
+
+fn next_field<'a>(fields: &mut std::str::Split<'a, char>, name: &str) -> Result<&'a str, String> {
+    fields.next().ok_or_else(|| format!("missing {}", name))
+}
+
+/// Parse an optional number column, empty when the entry has no metadata
+fn number_field<T>(
+    field: Option<&str>,
+    name: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match field {
+        None | Some("") => Ok(None),
+        Some(value) => parse(value)
+            .map(Some)
+            .ok_or_else(|| format!("invalid {} {}", name, value)),
+    }
+}
+
+/// Parse one entry line of a CSV manifest
+fn parse_line(line: &str) -> Result<MushLink, String> {
+    let mut fields = line.split(',');
+    let action = next_field(&mut fields, "action")?;
+    let action =
+        MushAction::from_string(action).ok_or_else(|| format!("unknown action {}", action))?;
+    let hash = next_field(&mut fields, "hash")?;
+    let src = decode_path(next_field(&mut fields, "src")?).map_err(|e| e.message)?;
+    let dst = decode_path(next_field(&mut fields, "dst")?).map_err(|e| e.message)?;
+    let dst_hash = fields.next().filter(|h| !h.is_empty());
+    //Version 1 manifests have no metadata columns
+    let size = number_field(fields.next(), "size", |s| s.parse().ok())?;
+    let mtime = number_field(fields.next(), "mtime", |s| s.parse().ok())?;
+    let mode = number_field(fields.next(), "mode", |s| u32::from_str_radix(s, 8).ok())?;
+    if fields.next().is_some() {
+        return Err(String::from(
+            "too many fields, commas in paths must be written as %2C",
+        ));
+    }
+    let meta = match (size, mtime, mode) {
+        (Some(size), Some(mtime), Some(mode)) => Some(FileMeta { size, mtime, mode }),
+        _ => None,
+    };
+
+    Ok(MushLink {
+        action,
+        hash: hash.to_owned(),
+        src,
+        dst,
+        dst_hash: dst_hash.map(String::from),
+        meta,
+        duplicate_count: None,
+    })
+}
+
+/// Error listing every entry of a manifest that could not be used
+pub(crate) fn invalid_entries(errors: Vec<String>) -> MushActionError {
+    MushActionError {
+        message: format!("Manifest has invalid entries:\n  {}", errors.join("\n  ")),
+    }
+}
+
+/// Collapse `.` and `..` components without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normal.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normal.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normal.push(".."),
+            },
+            c => normal.push(c),
+        }
+    }
+    normal
+}
+
+/// Check the file an entry writes to or deletes lies beneath the roots named in
+/// the header, so an edited manifest cannot reach outside them
+pub(crate) fn check_roots(header: &ManifestHeader, link: &MushLink) -> Result<(), String> {
+    let (side, path, roots) = match link.action {
+        MushAction::Add | MushAction::Update | MushAction::Collision => {
+            ("dst", &link.dst, vec![&header.dst])
+        }
+        MushAction::Remove if link.target() == link.dst => ("dst", &link.dst, vec![&header.dst]),
+        MushAction::Remove | MushAction::Retreive => {
+            ("src", &link.src, header.src.iter().collect())
+        }
+        _ => return Ok(()),
+    };
+    //Version 1 manifests do not name their roots
+    let roots: Vec<&String> = roots.into_iter().filter(|r| !r.is_empty()).collect();
+    let path = normalize(path);
+    if roots.is_empty()
+        || roots
+            .iter()
+            .any(|r| path.starts_with(normalize(Path::new(r))))
+    {
+        return Ok(());
+    }
+    let roots: Vec<&str> = roots.iter().map(|r| r.as_str()).collect();
+    Err(format!(
+        "{} {} is outside {}",
+        side,
+        path.display(),
+        roots.join(", ")
+    ))
+}
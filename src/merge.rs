@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::cache::Cache;
+use crate::incremental::Previous;
+use crate::prefilter::content_keys;
+use crate::{
+    FileMeta, Manifest, ManifestHeader, MushAction, MushActionError, MushLink, ScanOptions, Scanner,
+};
+
+/// Combine manifests scanned separately into one plan.
+///
+/// Every entry that reads a source file is decided again as if all the
+/// manifests had been a single scan, so duplicates, hash collisions and path
+/// conflicts are found across them. Ignore entries are kept as they are, so
+/// files left out by hand stay left out. Entries that do not read a source
+/// file, such as Remove, are dropped. The destination defaults to the one the
+/// manifests share. Files are keyed again across all the manifests, reusing
+/// the recorded hashes of files unchanged since they were scanned.
+pub fn merge<'a>(
+    inputs: Vec<(ManifestHeader, Vec<MushLink>)>,
+    dst: Option<String>,
+    manifest: &'a mut Manifest,
+    options: &ScanOptions,
+) -> Result<&'a Manifest, MushActionError> {
+    let dst = match dst {
+        Some(dst) => dst,
+        None => {
+            let dsts: HashSet<&String> = inputs.iter().map(|(header, _)| &header.dst).collect();
+            match dsts.into_iter().collect::<Vec<&String>>()[..] {
+                [dst] if !dst.is_empty() => dst.to_owned(),
+                _ => {
+                    return Err(MushActionError {
+                        message: String::from(
+                            "Manifests do not share a destination, choose one to merge into",
+                        ),
+                    })
+                }
+            }
+        }
+    };
+
+    info!("Merging {} manifests...", inputs.len());
+    let mut previous = Previous::default();
+    for (header, links) in &inputs {
+        header.hash_type()?;
+        previous.extend(Previous::new(header, links, options.hash));
+    }
+    let cache = Cache::open(options.hash_cache, &dst, previous);
+    let mut sources: Vec<String> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut dropped = 0;
+    let mut kept = Vec::new();
+
+    for (header, links) in inputs {
+        //Roots shared by several manifests are walked as one source
+        for root in &header.src {
+            if !sources.contains(root) {
+                sources.push(root.to_owned());
+            }
+        }
+        for link in links {
+            let reads_source = matches!(
+                link.action,
+                MushAction::Add
+                    | MushAction::Skip
+                    | MushAction::Update
+                    | MushAction::Collision
+                    | MushAction::Ignore
+            );
+            //The deepest source root holding the file, as scan would have walked it
+            let root = sources
+                .iter()
+                .enumerate()
+                .filter(|(_, root)| header.src.contains(root) && link.src.starts_with(root))
+                .max_by_key(|(_, root)| root.len());
+            let index = match (reads_source, root) {
+                (true, Some((index, _))) => index,
+                _ => {
+                    dropped += 1;
+                    continue;
+                }
+            };
+            //Overlapping scans list the same file more than once
+            if !seen.insert(link.src.to_owned()) {
+                continue;
+            }
+            if !link.src.is_file() {
+                return Err(MushActionError {
+                    message: format!(
+                        "Source file {} no longer exists, scan it again before merging",
+                        link.src.display()
+                    ),
+                });
+            }
+            kept.push((index, link));
+        }
+    }
+
+    //Keys taken within one manifest would not tell files of the others apart
+    let files: Vec<(PathBuf, Option<u64>)> = kept
+        .iter()
+        .map(|(_, link)| (link.src.to_owned(), FileMeta::of(&link.src).map(|m| m.size)))
+        .collect();
+    let keys = content_keys(&files, &[], options.hash, options.full_digests, &cache);
+
+    let mut scanner = Scanner::new(dst, options, None, &cache);
+    for (i, (index, link)) in kept.into_iter().enumerate() {
+        let terminal_msg = format!("\rMerging #{}...", i + 1);
+        print!("{}", terminal_msg);
+        std::io::stdout().flush().unwrap();
+        let hash = keys[&link.src].to_owned();
+        if link.action == MushAction::Ignore {
+            scanner.keep(MushLink { hash, ..link });
+            continue;
+        }
+        let root = &sources[index];
+        scanner.add(index, root, link.src, hash, link.meta, terminal_msg)?;
+    }
+
+    println!();
+    if dropped > 0 {
+        warning!("Dropped {} entries that do not read a source file", dropped);
+    }
+
+    scanner.finish(&sources, manifest)?;
+    cache.save();
+    Ok(manifest)
+}
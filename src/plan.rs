@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::{manifest_links, Manifest, MushAction, MushActionError, MushLink};
+
+/// Summary of what applying a manifest would do, built without touching the filesystem
+pub struct Plan {
+    /// Number of entries and bytes involved for each action
+    pub totals: BTreeMap<MushAction, (usize, u64)>,
+    /// Directories that would be created
+    pub new_dirs: BTreeSet<PathBuf>,
+    /// Entries whose hash matched a file with different content
+    pub collisions: Vec<MushLink>,
+}
+
+impl Plan {
+    /// Whether applying the manifest would change any file
+    pub fn has_changes(&self) -> bool {
+        self.totals.keys().any(|action| {
+            matches!(
+                action,
+                MushAction::Add
+                    | MushAction::Remove
+                    | MushAction::Update
+                    | MushAction::Retreive
+                    | MushAction::Collision
+            )
+        })
+    }
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Record every missing ancestor of the directory that will hold path
+fn add_missing_dirs(path: &Path, new_dirs: &mut BTreeSet<PathBuf>) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d.as_os_str().is_empty() || d.exists() {
+            break;
+        }
+        new_dirs.insert(d.to_path_buf());
+        dir = d.parent();
+    }
+}
+
+/// Build a plan summary of the manifest
+pub fn plan(manifest: &Manifest) -> Result<Plan, MushActionError> {
+    let mut plan = Plan {
+        totals: BTreeMap::new(),
+        new_dirs: BTreeSet::new(),
+        collisions: Vec::new(),
+    };
+
+    for link in manifest_links(manifest)? {
+        //Size of the file the action reads from, or deletes
+        let bytes = match link.meta {
+            Some(meta) => meta.size,
+            None => match link.action {
+                MushAction::Retreive => file_size(&link.dst),
+                MushAction::Remove => file_size(link.target()),
+                _ => file_size(&link.src),
+            },
+        };
+
+        match link.action {
+            MushAction::Add | MushAction::Update | MushAction::Collision => {
+                add_missing_dirs(&link.dst, &mut plan.new_dirs)
+            }
+            MushAction::Retreive => add_missing_dirs(&link.src, &mut plan.new_dirs),
+            _ => {}
+        }
+
+        let total = plan.totals.entry(link.action.clone()).or_insert((0, 0));
+        total.0 += 1;
+        total.1 += bytes;
+
+        if link.action == MushAction::Collision {
+            plan.collisions.push(link);
+        }
+    }
+
+    plan.collisions.sort_by(|a, b| a.src.cmp(&b.src));
+    Ok(plan)
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.totals.is_empty() {
+            return writeln!(f, "Nothing to do");
+        }
+
+        writeln!(f, "{}", style!("bold", "Plan"))?;
+        for (action, (count, bytes)) in &self.totals {
+            writeln!(f, "  {} {:>8} files {:>14} bytes", action, count, bytes)?;
+        }
+
+        if !self.new_dirs.is_empty() {
+            writeln!(f, "{}", style!("bold", "Directories to create"))?;
+            //Nest each directory beneath its parent when the parent is also new
+            let mut depths: BTreeMap<&Path, usize> = BTreeMap::new();
+            for dir in &self.new_dirs {
+                let parent_depth = dir.parent().and_then(|p| depths.get(p)).copied();
+                let (depth, name) = match parent_depth {
+                    Some(depth) => (depth + 1, dir.file_name().unwrap().to_string_lossy()),
+                    None => (1, dir.to_string_lossy()),
+                };
+                writeln!(f, "{}{}", "  ".repeat(depth), name)?;
+                depths.insert(dir, depth);
+            }
+        }
+
+        if !self.collisions.is_empty() {
+            writeln!(f, "{}", style!("bold", "Collisions"))?;
+            for link in &self.collisions {
+                writeln!(f, "  {} -> {}", link.src.display(), link.dst.display())?;
+            }
+        }
+
+        Ok(())
+    }
+}
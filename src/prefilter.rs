@@ -0,0 +1,141 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::cache::Cache;
+use crate::{get_file_hash, hash_reader, HashType};
+
+/// Prefix of hashes taken from the ends of a file rather than all of it
+pub(crate) const PARTIAL_PREFIX: &str = "partial:";
+
+/// Bytes read from each end of a file for its partial hash
+const PARTIAL_LEN: u64 = 4096;
+
+/// Hash of the size and the first and last `PARTIAL_LEN` bytes of a file
+pub(crate) fn get_partial_hash(path: &Path, hash_type: HashType) -> String {
+    let mut file = File::open(path).expect("Expected to open file");
+    let size = file.metadata().expect("Expected file metadata").len();
+    //The size is hashed too so files of different sizes never share a key
+    let mut sample = size.to_le_bytes().to_vec();
+    let head = size.min(PARTIAL_LEN);
+    (&mut file)
+        .take(head)
+        .read_to_end(&mut sample)
+        .expect("Expected to read file");
+    if size > head {
+        file.seek(SeekFrom::Start(size.saturating_sub(PARTIAL_LEN).max(head)))
+            .expect("Expected to seek file");
+        file.take(PARTIAL_LEN)
+            .read_to_end(&mut sample)
+            .expect("Expected to read file");
+    }
+    format!(
+        "{}{}",
+        PARTIAL_PREFIX,
+        hash_reader(sample.as_slice(), hash_type)
+    )
+}
+
+/// Whether reading the ends of a file costs much less than reading all of it
+fn is_large(size: Option<u64>) -> bool {
+    size.is_some_and(|size| size > PARTIAL_LEN * 2)
+}
+
+/// Key source files by their content while reading as little of each as
+/// possible, along with the dst files that could hold the same content as one
+/// of them.
+///
+/// Files are grouped by size first, a file alone in its size cannot have a
+/// duplicate and is keyed by a partial hash of its ends, which costs two small
+/// reads. Large files sharing a size are grouped again by that partial hash and
+/// only those still sharing a group are hashed in full. Small files sharing a
+/// size are hashed in full straight away, as reading their ends would cost
+/// about as much. Dst files only matter when they duplicate a source file, so
+/// those left alone in their group are not keyed at all. With full_digests
+/// every source file is keyed by its full hash. Files are hashed in parallel on
+/// the current thread pool, unless their hash is cached.
+pub(crate) fn content_keys(
+    sources: &[(PathBuf, Option<u64>)],
+    dst: &[(PathBuf, Option<u64>)],
+    hash_type: HashType,
+    full_digests: bool,
+    cache: &Cache,
+) -> HashMap<PathBuf, String> {
+    let full = |path: &Path| cache.full_hash(path, hash_type);
+    let partial = |path: &Path| cache.partial_hash(path, hash_type);
+    //Key of a source file nothing else is like
+    let alone = |path: &Path| match full_digests {
+        true => full(path),
+        false => partial(path),
+    };
+
+    //A dst file no source file shares a size with cannot be a duplicate
+    let sizes: HashSet<Option<u64>> = sources.iter().map(|(_, size)| *size).collect();
+    let files: Vec<(&PathBuf, Option<u64>, bool)> = sources
+        .iter()
+        .map(|(path, size)| (path, *size, true))
+        .chain(
+            dst.iter()
+                .filter(|(_, size)| size.is_none() || sizes.contains(size))
+                .map(|(path, size)| (path, *size, false)),
+        )
+        .collect();
+    let mut shared_sizes: HashMap<Option<u64>, usize> = HashMap::new();
+    for (_, size, _) in &files {
+        *shared_sizes.entry(*size).or_insert(0) += 1;
+    }
+    //Files of unknown size are never taken to be alone
+    let (unique, grouped): (Vec<_>, Vec<_>) = files
+        .iter()
+        .partition(|(_, size, _)| size.is_some() && shared_sizes[size] == 1);
+    let (large, small): (Vec<_>, Vec<_>) = grouped
+        .into_iter()
+        .partition(|(_, size, _)| is_large(*size));
+
+    let mut keys: HashMap<PathBuf, String> = unique
+        .par_iter()
+        .filter(|(_, _, source)| *source)
+        .map(|(path, _, _)| (path.to_path_buf(), alone(path)))
+        .collect();
+
+    //The size is part of the partial hash, so grouping by it keeps the size groups
+    let partials: Vec<(&PathBuf, bool, String)> = large
+        .par_iter()
+        .map(|(path, _, source)| (*path, *source, partial(path)))
+        .collect();
+    let mut shared_ends: HashMap<&str, usize> = HashMap::new();
+    for (_, _, ends) in &partials {
+        *shared_ends.entry(ends).or_insert(0) += 1;
+    }
+    let (unique, shared): (Vec<_>, Vec<_>) = partials
+        .iter()
+        .partition(|(_, _, ends)| shared_ends[ends.as_str()] == 1);
+    keys.par_extend(
+        unique.par_iter().filter(|(_, source, _)| *source).map(
+            |(path, _, ends)| match full_digests {
+                true => (path.to_path_buf(), full(path)),
+                false => (path.to_path_buf(), ends.to_owned()),
+            },
+        ),
+    );
+
+    keys.par_extend(
+        small
+            .par_iter()
+            .map(|(path, _, _)| *path)
+            .chain(shared.par_iter().map(|(path, _, _)| *path))
+            .map(|path| (path.to_owned(), full(path))),
+    );
+    keys
+}
+
+/// Hash of the file at path taken the same way as hash was, full or partial
+pub(crate) fn rehash_like(path: &Path, hash: &str, hash_type: HashType) -> String {
+    match hash.starts_with(PARTIAL_PREFIX) {
+        true => get_partial_hash(path, hash_type),
+        false => get_file_hash(path, Some(hash_type)),
+    }
+}
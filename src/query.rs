@@ -0,0 +1,95 @@
+use clap::ValueEnum;
+
+use crate::format::LinkRecord;
+use crate::{manifest_links, Manifest, MushAction, MushActionError, MushLink};
+
+/// Filters for `query`, an entry must pass every one that is set
+#[derive(Default)]
+pub struct Query {
+    /// Keep entries with one of these actions
+    pub actions: Vec<MushAction>,
+    /// Keep entries whose src or dst matches this glob pattern
+    pub glob: Option<String>,
+    /// Keep entries whose hash starts with this prefix
+    pub hash: Option<String>,
+    /// Keep entries of at least this many bytes
+    pub min_size: Option<u64>,
+    /// Keep entries of at most this many bytes
+    pub max_size: Option<u64>,
+}
+
+/// How `query` results are printed
+#[derive(Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum QueryOutput {
+    /// Aligned columns for reading
+    #[default]
+    Table,
+    /// Manifest entry lines
+    Csv,
+    /// A JSON array of entries with named fields
+    Json,
+}
+
+/// Entries of the manifest that pass every filter of the query, ordered by src
+pub fn query(manifest: &Manifest, query: &Query) -> Result<Vec<MushLink>, MushActionError> {
+    let pattern = match &query.glob {
+        Some(glob) => Some(glob::Pattern::new(glob).map_err(|e| MushActionError {
+            message: format!("Invalid glob {}: {}", glob, e),
+        })?),
+        None => None,
+    };
+
+    let mut links: Vec<MushLink> = manifest_links(manifest)?
+        .into_iter()
+        .filter(|link| query.actions.is_empty() || query.actions.contains(&link.action))
+        .filter(|link| match &pattern {
+            Some(p) => p.matches_path(&link.src) || p.matches_path(&link.dst),
+            None => true,
+        })
+        .filter(|link| match &query.hash {
+            Some(prefix) => link.hash.starts_with(prefix),
+            None => true,
+        })
+        .filter(|link| {
+            //Entries without metadata only pass when no size is asked for
+            let size = link.meta.map(|m| m.size);
+            let above = query
+                .min_size
+                .is_none_or(|min| size.is_some_and(|s| s >= min));
+            let below = query
+                .max_size
+                .is_none_or(|max| size.is_some_and(|s| s <= max));
+            above && below
+        })
+        .collect();
+    links.sort_by(|a, b| a.src.cmp(&b.src).then_with(|| a.dst.cmp(&b.dst)));
+    Ok(links)
+}
+
+/// Print links in the chosen output
+pub fn render(links: &[MushLink], output: QueryOutput) -> String {
+    match output {
+        QueryOutput::Table => {
+            let mut table = String::new();
+            for link in links {
+                let size = match link.meta {
+                    Some(meta) => meta.size.to_string(),
+                    None => String::from("-"),
+                };
+                table += &format!(
+                    "{} {:>14}  {} -> {}\n",
+                    link.action,
+                    size,
+                    link.src.display(),
+                    link.dst.display()
+                );
+            }
+            table
+        }
+        QueryOutput::Csv => links.iter().map(|link| format!("{}\n", link)).collect(),
+        QueryOutput::Json => {
+            let records: Vec<LinkRecord> = links.iter().map(LinkRecord::new).collect();
+            serde_json::to_string_pretty(&records).expect("Expected entries to serialize") + "\n"
+        }
+    }
+}
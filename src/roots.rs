@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{path_bytes, path_from_bytes};
+use crate::{ManifestHeader, MushActionError, MushLink};
+
+impl ManifestHeader {
+    /// Every root with its name, `dst` for the destination and `src0`, `src1`
+    /// and so on for the sources in the order they were scanned
+    pub fn roots(&self) -> Vec<(String, PathBuf)> {
+        let mut roots = vec![(String::from("dst"), PathBuf::from(&self.dst))];
+        for (index, src) in self.src.iter().enumerate() {
+            roots.push((format!("src{}", index), PathBuf::from(src)));
+        }
+        roots.retain(|(_, root)| !root.as_os_str().is_empty());
+        roots
+    }
+}
+
+/// Write a path beneath one of the roots as `<root name>:<relative path>`,
+/// picking the deepest root when they are nested
+fn to_root(roots: &[(String, PathBuf)], path: &Path) -> PathBuf {
+    let nearest = roots
+        .iter()
+        .filter_map(|(name, root)| path.strip_prefix(root).ok().map(|rel| (name, root, rel)))
+        .max_by_key(|(_, root, _)| root.components().count());
+    match nearest {
+        Some((name, _, rel)) => {
+            let mut bytes = format!("{}:", name).into_bytes();
+            bytes.extend_from_slice(&path_bytes(rel));
+            path_from_bytes(bytes).unwrap_or_else(|_| path.to_path_buf())
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+/// Resolve a path written by `to_root` against the roots
+fn from_root(roots: &[(String, PathBuf)], path: &Path) -> Result<PathBuf, MushActionError> {
+    let bytes = path_bytes(path);
+    let resolved = bytes.iter().position(|&b| b == b':').and_then(|colon| {
+        let name = std::str::from_utf8(&bytes[..colon]).ok()?;
+        let (_, root) = roots.iter().find(|(n, _)| n == name)?;
+        Some((root, bytes[colon + 1..].to_vec()))
+    });
+    match resolved {
+        Some((root, rel)) if rel.is_empty() => Ok(root.to_owned()),
+        Some((root, rel)) => Ok(root.join(path_from_bytes(rel)?)),
+        None => Ok(path.to_path_buf()),
+    }
+}
+
+/// Copy of link with its paths relative to the header roots, as it is stored
+pub(crate) fn link_to_roots(header: &ManifestHeader, link: &MushLink) -> MushLink {
+    let roots = header.roots();
+    let mut link = link.clone();
+    for path in [&mut link.src, &mut link.dst] {
+        if !path.as_os_str().is_empty() {
+            *path = to_root(&roots, path);
+        }
+    }
+    link
+}
+
+/// Resolve the stored paths of link against the header roots
+pub(crate) fn link_from_roots(
+    header: &ManifestHeader,
+    mut link: MushLink,
+) -> Result<MushLink, String> {
+    let roots = header.roots();
+    link.src = from_root(&roots, &link.src).map_err(|e| e.message)?;
+    link.dst = from_root(&roots, &link.dst).map_err(|e| e.message)?;
+    Ok(link)
+}
+
+/// Move the roots of a manifest read on another machine or mount point.
+///
+/// Each src value is `NAME=PATH` for a source root named in the header, or a
+/// bare `PATH` when the manifest has a single source root. Paths beneath a
+/// moved root are moved with it and every other path is left as it is.
+pub fn rebase(
+    header: &ManifestHeader,
+    links: Vec<MushLink>,
+    src: &[String],
+    dst: Option<&str>,
+) -> Result<(ManifestHeader, Vec<MushLink>), MushActionError> {
+    let mut rebased = header.clone();
+    for value in src {
+        let (index, path) = match value.split_once('=') {
+            Some((name, path)) => {
+                let index = name
+                    .strip_prefix("src")
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .filter(|&i| i < header.src.len())
+                    .ok_or_else(|| MushActionError {
+                        message: format!("Manifest has no source root named {}", name),
+                    })?;
+                (index, path)
+            }
+            None if header.src.len() == 1 => (0, value.as_str()),
+            None => {
+                return Err(MushActionError {
+                    message: format!(
+                        "Manifest has {} source roots, name the one to rebase as src0=PATH",
+                        header.src.len()
+                    ),
+                })
+            }
+        };
+        rebased.src[index] = path.to_owned();
+    }
+    if let Some(dst) = dst {
+        rebased.dst = dst.to_owned();
+    }
+
+    let links = links
+        .iter()
+        .map(|link| link_from_roots(&rebased, link_to_roots(header, link)))
+        .collect::<Result<Vec<MushLink>, String>>()
+        .map_err(|message| MushActionError { message })?;
+    Ok((rebased, links))
+}
@@ -0,0 +1,357 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::format::ManifestFormat;
+use crate::manifest::{invalid_entries, parse_entries};
+use crate::{ManifestHeader, MushActionError};
+
+/// Last line of a manifest file, or the `trailer` field of a JSON document, so
+/// a truncated or altered manifest is refused
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Trailer {
+    /// Number of entries before the trailer
+    entries: usize,
+    /// `blake3:` checksum of every byte before the trailer, or of the rest of
+    /// a JSON document written compactly
+    checksum: String,
+    /// Signature of the entry count and checksum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<TrailerSignature>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrailerSignature {
+    /// Hex ed25519 public key of the signer
+    key: String,
+    /// Hex ed25519 signature
+    value: String,
+}
+
+/// Trailer as the last line of NDJSON manifests
+#[derive(Serialize, Deserialize)]
+struct JsonTrailer {
+    trailer: Trailer,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn checksum(body: &[u8]) -> String {
+    format!("blake3:{}", blake3::hash(body).to_hex())
+}
+
+impl Trailer {
+    pub(crate) fn new(body: &[u8], entries: usize) -> Trailer {
+        Trailer {
+            entries,
+            checksum: checksum(body),
+            signature: None,
+        }
+    }
+
+    /// What the signature covers
+    fn message(&self) -> String {
+        format!("{} {}", self.entries, self.checksum)
+    }
+
+    fn sign(&mut self, key: &SigningKey) {
+        self.signature = Some(TrailerSignature {
+            key: to_hex(key.verifying_key().as_bytes()),
+            value: to_hex(&key.sign(self.message().as_bytes()).to_bytes()),
+        });
+    }
+
+    /// Check the signature against the public key it names
+    fn verify(&self, signature: &TrailerSignature) -> Result<(), String> {
+        let key = from_hex::<32>(&signature.key)
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .ok_or("signature has an invalid public key")?;
+        let value = from_hex::<64>(&signature.value).ok_or("signature is not valid hex")?;
+        key.verify_strict(self.message().as_bytes(), &Signature::from_bytes(&value))
+            .map_err(|_| String::from("signature does not match the manifest"))
+    }
+
+    /// Line written after the body, a comment where the format has them
+    fn line(self, format: ManifestFormat) -> String {
+        let json = match format {
+            ManifestFormat::Ndjson => serde_json::to_string(&JsonTrailer { trailer: self }),
+            _ => serde_json::to_string(&self),
+        }
+        .expect("Expected trailer to serialize");
+        match format {
+            ManifestFormat::Ndjson => format!("{}\n", json),
+            _ => format!("#trailer {}\n", json),
+        }
+    }
+
+    fn parse(line: &[u8], format: ManifestFormat) -> Result<Trailer, String> {
+        let parsed = match format {
+            ManifestFormat::Csv | ManifestFormat::Toml => {
+                serde_json::from_slice(&line[TRAILER_COMMENT.len()..])
+            }
+            ManifestFormat::Ndjson => {
+                serde_json::from_slice(line).map(|json: JsonTrailer| json.trailer)
+            }
+            ManifestFormat::Json => serde_json::from_slice(line),
+        };
+        parsed.map_err(|e| format!("unreadable: {}", e))
+    }
+}
+
+/// Bytes of a manifest file holding body sealed by a trailer, signed with key
+/// when one is given. The trailer of a JSON document goes inside it.
+pub(crate) fn sealed(
+    body: &[u8],
+    entries: usize,
+    format: ManifestFormat,
+    key: Option<&SigningKey>,
+) -> Vec<u8> {
+    let mut document = match format {
+        ManifestFormat::Json => {
+            Some(serde_json::from_slice::<Value>(body).expect("Expected manifest document"))
+        }
+        _ => None,
+    };
+    let covered = match &document {
+        Some(document) => Cow::Owned(compact(document)),
+        None => Cow::Borrowed(body),
+    };
+    let mut trailer = Trailer::new(&covered, entries);
+    if let Some(key) = key {
+        trailer.sign(key);
+    }
+    match document.as_mut().and_then(Value::as_object_mut) {
+        Some(fields) => {
+            let trailer = serde_json::to_value(&trailer).expect("Expected trailer to serialize");
+            fields.insert(String::from("trailer"), trailer);
+            let mut bytes = serde_json::to_vec_pretty(&document).expect("Expected manifest");
+            bytes.push(b'\n');
+            bytes
+        }
+        None => {
+            let mut bytes = body.to_vec();
+            bytes.extend_from_slice(trailer.line(format).as_bytes());
+            bytes
+        }
+    }
+}
+
+/// A JSON document written without whitespace, what its checksum covers so it
+/// stays sealed when reformatted
+fn compact(document: &Value) -> Vec<u8> {
+    serde_json::to_vec(document).expect("Expected manifest to serialize")
+}
+
+const TRAILER_COMMENT: &[u8] = b"#trailer ";
+const TRAILER_JSON: &[u8] = b"{\"trailer\":";
+
+/// Split the bytes of a manifest file into the body its checksum covers and
+/// its trailer
+pub(crate) fn split_trailer(
+    bytes: &[u8],
+    format: ManifestFormat,
+) -> (Cow<'_, [u8]>, Option<Cow<'_, [u8]>>) {
+    if format == ManifestFormat::Json {
+        return split_document(bytes);
+    }
+    let end = bytes
+        .iter()
+        .rposition(|&b| b != b'\n' && b != b'\r')
+        .map_or(0, |i| i + 1);
+    let start = bytes[..end]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let line = &bytes[start..end];
+    let prefix = match format {
+        ManifestFormat::Ndjson => TRAILER_JSON,
+        _ => TRAILER_COMMENT,
+    };
+    match line.starts_with(prefix) {
+        true => (Cow::Borrowed(&bytes[..start]), Some(Cow::Borrowed(line))),
+        false => (Cow::Borrowed(bytes), None),
+    }
+}
+
+/// Split a JSON document into the rest of it written compactly and its
+/// `trailer` field
+fn split_document(bytes: &[u8]) -> (Cow<'_, [u8]>, Option<Cow<'_, [u8]>>) {
+    let mut document: Value = match serde_json::from_slice(bytes) {
+        Ok(document) => document,
+        //Left for the manifest reader to report
+        Err(_) => return (Cow::Borrowed(bytes), None),
+    };
+    let trailer = document
+        .as_object_mut()
+        .and_then(|fields| fields.shift_remove("trailer"));
+    let trailer = trailer.map(|trailer| Cow::Owned(compact(&trailer)));
+    (Cow::Owned(compact(&document)), trailer)
+}
+
+/// Problem with the trailer of a manifest whose body holds entries
+pub(crate) fn check_trailer(
+    header: &ManifestHeader,
+    body: &[u8],
+    line: Option<&[u8]>,
+    format: ManifestFormat,
+    entries: usize,
+) -> Option<String> {
+    let trailer = match line {
+        //Manifests from before the header existed cannot carry a trailer
+        None if header.version == 1 => {
+            warning!("Manifest has no header, it cannot be checked for truncation or tampering");
+            return None;
+        }
+        None => {
+            return Some(String::from(
+                "missing, the manifest may have been cut short",
+            ))
+        }
+        Some(line) => match Trailer::parse(line, format) {
+            Ok(trailer) => trailer,
+            Err(e) => return Some(e),
+        },
+    };
+    if trailer.checksum != checksum(body) {
+        return Some(String::from(
+            "checksum does not match, the manifest changed after it was written, seal it again if the change was intended",
+        ));
+    }
+    if trailer.entries != entries {
+        return Some(format!(
+            "records {} entries but the manifest has {}",
+            trailer.entries, entries
+        ));
+    }
+    trailer
+        .signature
+        .as_ref()
+        .and_then(|s| trailer.verify(s).err())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, MushActionError> {
+    std::fs::read(path).map_err(|e| MushActionError {
+        message: format!("Failed to read {}: {}", path.display(), e),
+    })
+}
+
+/// Read a key file holding a hex key
+fn read_key<const N: usize>(path: &Path) -> Result<[u8; N], MushActionError> {
+    let text = String::from_utf8(read_file(path)?).unwrap_or_default();
+    from_hex::<N>(&text).ok_or_else(|| MushActionError {
+        message: format!("{} does not hold a {} byte hex key", path.display(), N),
+    })
+}
+
+/// Write a new trailer for a manifest file, signing it with the secret key
+/// file when one is given. Used after editing a manifest by hand, returns the
+/// number of entries sealed.
+pub fn seal(
+    path: &Path,
+    format: ManifestFormat,
+    key: Option<&Path>,
+) -> Result<usize, MushActionError> {
+    let bytes = read_file(path)?;
+    let (body, _) = split_trailer(&bytes, format);
+    let (_, links, errors) = parse_entries(&body, format)?;
+    if !errors.is_empty() {
+        return Err(invalid_entries(errors));
+    }
+
+    let key = match key {
+        Some(key) => Some(SigningKey::from_bytes(&read_key::<32>(key)?)),
+        None => None,
+    };
+    std::fs::write(path, sealed(&body, links.len(), format, key.as_ref())).map_err(|e| {
+        MushActionError {
+            message: format!("Failed to write {}: {}", path.display(), e),
+        }
+    })?;
+    Ok(links.len())
+}
+
+/// Create a key pair for signing manifests. The secret key is written to
+/// path and the public key beside it with a `.pub` extension, whose path is
+/// returned.
+pub fn generate_key(path: &Path) -> Result<PathBuf, MushActionError> {
+    let failed = |path: &Path, e: &dyn std::fmt::Display| MushActionError {
+        message: format!("Failed to write {}: {}", path.display(), e),
+    };
+    let mut seed = [0; 32];
+    getrandom::fill(&mut seed).map_err(|e| MushActionError {
+        message: format!("Failed to generate a key: {}", e),
+    })?;
+    let key = SigningKey::from_bytes(&seed);
+
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", to_hex(&seed)))
+        .map_err(|e| failed(path, &e))?;
+
+    let mut public = path.as_os_str().to_owned();
+    public.push(".pub");
+    let public = PathBuf::from(public);
+    std::fs::write(
+        &public,
+        format!("{}\n", to_hex(key.verifying_key().as_bytes())),
+    )
+    .map_err(|e| failed(&public, &e))?;
+    Ok(public)
+}
+
+/// Refuse a manifest file that is not signed by the public key file
+pub fn check_signature(
+    path: &Path,
+    format: ManifestFormat,
+    public_key: &Path,
+) -> Result<(), MushActionError> {
+    let trusted = to_hex(&read_key::<32>(public_key)?);
+    let unsigned = |reason: String| MushActionError {
+        message: format!(
+            "Manifest {} is not signed by {}: {}",
+            path.display(),
+            public_key.display(),
+            reason
+        ),
+    };
+
+    let bytes = read_file(path)?;
+    let (body, line) = split_trailer(&bytes, format);
+    let trailer = Trailer::parse(
+        &line.ok_or_else(|| unsigned(String::from("it has no trailer")))?,
+        format,
+    )
+    .map_err(|e| unsigned(format!("trailer {}", e)))?;
+    let signature = match &trailer.signature {
+        Some(signature) if signature.key == trusted => signature,
+        Some(_) => return Err(unsigned(String::from("it is signed by another key"))),
+        None => return Err(unsigned(String::from("it has no signature"))),
+    };
+    if trailer.checksum != checksum(&body) {
+        return Err(unsigned(String::from("it changed after it was signed")));
+    }
+    trailer.verify(signature).map_err(unsigned)
+}
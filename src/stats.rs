@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::{manifest_links, Manifest, MushAction, MushActionError};
+
+/// Content found more than once across the sources
+pub struct DuplicateGroup {
+    /// Where the single kept copy goes
+    pub dst: PathBuf,
+    /// Number of source files with this content
+    pub files: usize,
+    /// Bytes not copied because of the duplicates
+    pub saved: u64,
+}
+
+/// Figures describing a manifest, taken from its recorded metadata
+pub struct Stats {
+    /// Number of entries and bytes for each action
+    pub totals: BTreeMap<MushAction, (usize, u64)>,
+    /// Bytes that applying the manifest would copy or move
+    pub bytes_to_copy: u64,
+    /// Bytes not copied because they duplicate other source files
+    pub bytes_saved: u64,
+    /// Duplicate groups saving the most bytes, largest first
+    pub top_duplicates: Vec<DuplicateGroup>,
+}
+
+/// Summarise a manifest, keeping the top largest duplicate groups
+pub fn stats(manifest: &Manifest, top: usize) -> Result<Stats, MushActionError> {
+    let mut totals: BTreeMap<MushAction, (usize, u64)> = BTreeMap::new();
+    let mut bytes_to_copy = 0;
+    let mut bytes_saved = 0;
+    let mut groups: HashMap<String, DuplicateGroup> = HashMap::new();
+
+    for link in manifest_links(manifest)? {
+        let size = link.meta.map(|m| m.size).unwrap_or(0);
+        let total = totals.entry(link.action.clone()).or_insert((0, 0));
+        total.0 += 1;
+        total.1 += size;
+
+        match link.action {
+            MushAction::Add | MushAction::Update | MushAction::Collision | MushAction::Retreive => {
+                bytes_to_copy += size
+            }
+            //Scan marks duplicates of an earlier source file as hash[dN]
+            MushAction::Skip if link.hash.contains("[d") => {
+                bytes_saved += size;
+                let hash = link.hash.split('[').next().unwrap_or(&link.hash);
+                let group = groups.entry(hash.to_owned()).or_insert(DuplicateGroup {
+                    dst: link.dst.to_owned(),
+                    //The original counts as one of the files
+                    files: 1,
+                    saved: 0,
+                });
+                group.files += 1;
+                group.saved += size;
+            }
+            _ => {}
+        }
+    }
+
+    let mut top_duplicates: Vec<DuplicateGroup> = groups.into_values().collect();
+    top_duplicates.sort_by(|a, b| b.saved.cmp(&a.saved).then_with(|| a.dst.cmp(&b.dst)));
+    top_duplicates.truncate(top);
+
+    Ok(Stats {
+        totals,
+        bytes_to_copy,
+        bytes_saved,
+        top_duplicates,
+    })
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", style!("bold", "Entries"))?;
+        for (action, (count, bytes)) in &self.totals {
+            writeln!(f, "  {} {:>8} files {:>14} bytes", action, count, bytes)?;
+        }
+        writeln!(f, "{}", style!("bold", "Bytes"))?;
+        writeln!(f, "  to copy {:>14}", self.bytes_to_copy)?;
+        writeln!(f, "  saved   {:>14}", self.bytes_saved)?;
+
+        if !self.top_duplicates.is_empty() {
+            writeln!(f, "{}", style!("bold", "Top duplicates"))?;
+            for group in &self.top_duplicates {
+                writeln!(
+                    f,
+                    "  {:>8} files {:>14} bytes saved  {}",
+                    group.files,
+                    group.saved,
+                    group.dst.display()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    decode_path, encode_path, get_file_hash, walk_files, write_manifest, FileMeta, Manifest,
+    ManifestHeader, MushAction, MushLink, MushOutcome, MUSH_DIR,
+};
+
+/// File inside the destination's mush directory holding the last synced state
+const SYNC_STATE: &str = "sync.state";
+
+/// Hashes of every file that was identical on both sides after the last sync
+struct SyncState {
+    src: String,
+    hashes: HashMap<PathBuf, String>,
+}
+
+fn state_path(dst: &str) -> PathBuf {
+    Path::new(dst).join(MUSH_DIR).join(SYNC_STATE)
+}
+
+/// Load the state saved by the last sync of src into dst, if there was one
+fn load_state(src: &str, dst: &str) -> Option<SyncState> {
+    let file = File::open(state_path(dst)).ok()?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines.next()?.ok()?;
+    let state_src = decode_path(header.strip_prefix("#src ")?).ok()?;
+    if state_src != Path::new(src) {
+        warning!(
+            "Ignoring sync state for {}, it was saved for {}",
+            dst,
+            state_src.display()
+        );
+        return None;
+    }
+
+    let mut hashes = HashMap::new();
+    for line in lines {
+        let line = line.ok()?;
+        let (hash, rel) = line.split_once(',')?;
+        hashes.insert(decode_path(rel).ok()?, hash.to_owned());
+    }
+
+    Some(SyncState {
+        src: src.to_owned(),
+        hashes,
+    })
+}
+
+fn save_state(dst: &str, state: &SyncState) -> std::io::Result<()> {
+    let path = state_path(dst);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+
+    let mut rels: Vec<&PathBuf> = state.hashes.keys().collect();
+    rels.sort();
+
+    let mut file = File::create(path)?;
+    writeln!(file, "#src {}", encode_path(Path::new(&state.src)))?;
+    for rel in rels {
+        writeln!(file, "{},{}", state.hashes[rel], encode_path(rel))?;
+    }
+    Ok(())
+}
+
+/// Hash every file beneath root, keyed by its path relative to root
+fn hash_tree(root: &str) -> HashMap<PathBuf, String> {
+    walk_files(root)
+        .map(|file| {
+            let rel = file.path().strip_prefix(root).unwrap().to_path_buf();
+            (rel, get_file_hash(file.path(), None))
+        })
+        .collect()
+}
+
+/// Compare src, dst and the state saved by the last sync and plan the changes
+/// needed to bring both sides back in line.
+///
+/// Files changed on one side only are copied across with Add, Update or
+/// Retreive, files deleted on one side only are removed from the other and
+/// files changed on both sides are flagged as a Conflict and left alone.
+pub fn sync<'a>(src: &str, dst: &str, manifest: &'a mut Manifest) -> &'a Manifest {
+    info!("Syncing {} with {}...", src, dst);
+
+    let base = load_state(src, dst).map(|s| s.hashes).unwrap_or_default();
+    let src_hashes = hash_tree(src);
+    let dst_hashes = hash_tree(dst);
+
+    let rels: BTreeSet<&PathBuf> = src_hashes
+        .keys()
+        .chain(dst_hashes.keys())
+        .chain(base.keys())
+        .collect();
+
+    let mut links = Vec::new();
+    for rel in rels {
+        let src_path = Path::new(src).join(rel);
+        let dst_path = Path::new(dst).join(rel);
+        let s = src_hashes.get(rel);
+        let d = dst_hashes.get(rel);
+        let b = base.get(rel);
+
+        let (action, hash, dst_hash) = match (s, d) {
+            (Some(s), Some(d)) if s == d => (MushAction::Skip, s, None),
+            (Some(s), Some(d)) if b == Some(s) => (MushAction::Retreive, d, None),
+            (Some(s), Some(d)) if b == Some(d) => (MushAction::Update, s, Some(d)),
+            (Some(s), Some(d)) => (MushAction::Conflict, s, Some(d)),
+            (Some(s), None) if b.is_none() => (MushAction::Add, s, None),
+            (Some(s), None) if b == Some(s) => (MushAction::Remove, s, None),
+            (Some(s), None) => (MushAction::Conflict, s, None),
+            (None, Some(d)) if b.is_none() => (MushAction::Retreive, d, None),
+            (None, Some(d)) if b == Some(d) => (MushAction::Remove, d, None),
+            (None, Some(d)) => (MushAction::Conflict, d, Some(d)),
+            (None, None) => continue,
+        };
+
+        //A Remove names only the side it deletes from, see MushLink::target
+        let (src_path, dst_path) = match (&action, s, d) {
+            (MushAction::Remove, Some(_), None) => (src_path, PathBuf::new()),
+            (MushAction::Remove, None, Some(_)) => (PathBuf::new(), dst_path),
+            _ => (src_path, dst_path),
+        };
+
+        //Metadata of the side the action reads from, or deletes
+        let meta = match (&action, s) {
+            (MushAction::Retreive, _) | (MushAction::Remove, None) => {
+                FileMeta::of(&Path::new(dst).join(rel))
+            }
+            _ => FileMeta::of(&Path::new(src).join(rel)),
+        };
+
+        let mushlink = MushLink {
+            action,
+            hash: hash.to_owned(),
+            src: src_path,
+            dst: dst_path,
+            dst_hash: dst_hash.cloned(),
+            meta,
+            duplicate_count: None,
+        };
+        links.push((encode_path(rel), mushlink));
+    }
+
+    let header = ManifestHeader::new(&[src.to_owned()], dst);
+    if let Err(e) = write_manifest(manifest, &header, links) {
+        error!("{}", e);
+    }
+    manifest
+}
+
+/// Save the state left behind by applying a sync plan so the next sync can
+/// tell which side changed. The state is rebuilt from the plan, so files gone
+/// from both sides are forgotten rather than deleted when they reappear.
+/// Entries that failed or conflicted keep their previous base so they are
+/// reconsidered next time.
+pub fn save_sync_state(src: &str, dst: &str, outcomes: &[(MushLink, MushOutcome)]) {
+    let base = load_state(src, dst).map(|s| s.hashes).unwrap_or_default();
+    let mut state = SyncState {
+        src: src.to_owned(),
+        hashes: HashMap::new(),
+    };
+
+    for (link, outcome) in outcomes {
+        let rel = match link.target() == link.dst {
+            true => link.dst.strip_prefix(dst),
+            false => link.src.strip_prefix(src),
+        };
+        let rel = match rel {
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => continue,
+        };
+
+        match (&link.action, outcome) {
+            (_, MushOutcome::Failed(_)) | (MushAction::Conflict, _) => {
+                if let Some(hash) = base.get(&rel) {
+                    state.hashes.insert(rel, hash.to_owned());
+                }
+            }
+            (_, MushOutcome::Removed) | (_, MushOutcome::Trashed) => {}
+            _ => {
+                state.hashes.insert(rel, link.hash.to_owned());
+            }
+        }
+    }
+
+    if let Err(e) = save_state(dst, &state) {
+        error!("Failed to save sync state for {}: {}", dst, e);
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::read_entries;
+use crate::prefilter::{rehash_like, PARTIAL_PREFIX};
+use crate::{get_file_hash, FileMeta, HashType, Manifest, MushAction, MushActionError, MushLink};
+
+/// Result of checking a manifest against the files it names
+pub struct Validation {
+    /// Number of entries that could be read
+    pub checked: usize,
+    /// Every problem found
+    pub problems: Vec<String>,
+}
+
+impl Validation {
+    /// Whether the manifest can still be applied as it was planned
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Whether the file at path still hashes to the recorded hash
+fn hash_matches(path: &Path, hash: &str, hash_type: HashType) -> bool {
+    //Scan tells duplicates and collisions apart with a bracketed suffix
+    let hash = hash.split('[').next().unwrap_or(hash);
+    let path = path.to_path_buf();
+    match hash.strip_prefix("blake3:") {
+        Some(digest) => get_file_hash(&path, Some(HashType::Blake3)) == digest,
+        None => rehash_like(&path, hash, hash_type) == hash,
+    }
+}
+
+/// Whether a file keyed by a partial hash was modified since the scan, as the
+/// ends of a file miss a change in its middle
+fn touched(path: &Path, link: &MushLink) -> bool {
+    match (link.meta, FileMeta::of(path)) {
+        (Some(then), Some(now)) => link.hash.starts_with(PARTIAL_PREFIX) && then.mtime != now.mtime,
+        _ => false,
+    }
+}
+
+/// Check a manifest against the filesystem without changing anything.
+///
+/// Reports entries that cannot be read or reach outside their roots, source
+/// files that are missing or changed since the scan, destinations that changed
+/// under an Add or Update and paths written by more than one entry.
+pub fn validate(manifest: &Manifest) -> Result<Validation, MushActionError> {
+    let (links, mut problems, hash_type) = match manifest {
+        Manifest::File(ref file, format) => {
+            let (header, links, errors) = read_entries(file, *format)?;
+            (links, errors, header.hash_type()?)
+        }
+        Manifest::Map(ref map) => (
+            map.values().cloned().collect(),
+            Vec::new(),
+            HashType::default(),
+        ),
+    };
+
+    //Every path an entry writes to, with how many entries write it
+    let mut writes: BTreeMap<&PathBuf, usize> = BTreeMap::new();
+    for link in &links {
+        //The file the action reads from, or deletes
+        let (side, path) = match link.action {
+            MushAction::Conflict | MushAction::Ignore => continue,
+            MushAction::Retreive => ("dst", &link.dst),
+            MushAction::Remove if link.target() == link.dst => ("dst", &link.dst),
+            _ => ("src", &link.src),
+        };
+        if !path.is_file() {
+            problems.push(format!("{} {} no longer exists", side, path.display()));
+        } else if !hash_matches(path, &link.hash, hash_type) || touched(path, link) {
+            problems.push(format!(
+                "{} {} changed since the scan",
+                side,
+                path.display()
+            ));
+        }
+
+        match (&link.action, &link.dst_hash) {
+            (MushAction::Add, _) if link.dst.exists() => {
+                problems.push(format!("dst {} exists since the scan", link.dst.display()))
+            }
+            (MushAction::Update, _) if !link.dst.is_file() => {
+                problems.push(format!("dst {} no longer exists", link.dst.display()))
+            }
+            (MushAction::Update, Some(dst_hash))
+                if !hash_matches(&link.dst, dst_hash, hash_type) =>
+            {
+                problems.push(format!("dst {} changed since the scan", link.dst.display()))
+            }
+            _ => {}
+        }
+
+        let target = match link.action {
+            MushAction::Add | MushAction::Update | MushAction::Collision => &link.dst,
+            MushAction::Retreive => &link.src,
+            _ => continue,
+        };
+        *writes.entry(target).or_insert(0) += 1;
+    }
+
+    for (target, count) in writes {
+        if count > 1 {
+            problems.push(format!(
+                "{} is written by {} entries",
+                target.display(),
+                count
+            ));
+        }
+    }
+
+    Ok(Validation {
+        checked: links.len(),
+        problems,
+    })
+}
+
+impl std::fmt::Display for Validation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "  {}", problem)?;
+        }
+        match self.passed() {
+            true => writeln!(
+                f,
+                "{} {} entries checked",
+                style!("bold,green", "PASS"),
+                self.checked
+            ),
+            false => writeln!(
+                f,
+                "{} {} problems found in {} entries",
+                style!("bold,red", "FAIL"),
+                self.problems.len(),
+                self.checked
+            ),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::MUSH_DIR;
+
+/// Every file beneath root in path order, reading directories in parallel.
+///
+/// Like `walk_files` it skips mush's own state directory and does not descend
+/// into linked directories, the order never depends on which thread finished
+/// first.
+pub(crate) fn walk_sorted(root: &Path) -> Vec<PathBuf> {
+    if root.is_file() {
+        return vec![root.to_path_buf()];
+    }
+    let mut files = walk_dir(root);
+    files.sort();
+    files
+}
+
+fn walk_dir(dir: &Path) -> Vec<PathBuf> {
+    let entries: Vec<std::fs::DirEntry> = std::fs::read_dir(dir)
+        .expect("Expected to read directory")
+        .map(|e| e.expect("Expected file"))
+        .filter(|e| e.file_name() != MUSH_DIR)
+        .collect();
+    entries
+        .par_iter()
+        .flat_map_iter(|entry| {
+            let path = entry.path();
+            match entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                true => walk_dir(&path),
+                false if path.is_file() => vec![path],
+                false => Vec::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use common::{in_memory, links, scan_into, write_settled};
+use mush::{HashCache, MushLink, ScanOptions};
+use tempfile::TempDir;
+
+fn scan_cached(src: &Path, dst: &Path) -> HashMap<String, MushLink> {
+    let options = ScanOptions {
+        hash_cache: HashCache::Dst,
+        ..Default::default()
+    };
+    links(scan_into(in_memory(), &[src], dst, &options))
+}
+
+fn hash_of(links: &HashMap<String, MushLink>, path: &Path) -> String {
+    links
+        .values()
+        .find(|link| link.src == path)
+        .map(|link| link.hash.to_owned())
+        .unwrap()
+}
+
+#[test]
+fn unchanged_files_are_not_hashed_again() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let settled = src.path().join("settled.txt");
+    let fresh = src.path().join("fresh.txt");
+    write_settled(&settled, b"settled");
+    fs::write(&fresh, b"fresh").unwrap();
+
+    let links = scan_cached(src.path(), dst.path());
+    let real = hash_of(&links, &settled);
+    let cache = dst.path().join(".mush/hashes");
+    let contents = fs::read_to_string(&cache).unwrap();
+    assert_eq!(contents.lines().count(), 1, "{}", contents);
+    assert!(contents.contains(&real));
+
+    //A rescan takes the hash from the cache without reading the file
+    fs::write(&cache, contents.replace(&real, "cached")).unwrap();
+    let links = scan_cached(src.path(), dst.path());
+    assert_eq!(hash_of(&links, &settled), "cached");
+
+    //Changing the file makes its entry stale
+    write_settled(&settled, b"changed");
+    let links = scan_cached(src.path(), dst.path());
+    assert_ne!(hash_of(&links, &settled), "cached");
+    assert_ne!(hash_of(&links, &settled), real);
+}
+
+#[test]
+fn dst_files_are_cached_too() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write_settled(&src.path().join("a.txt"), b"new");
+    write_settled(&dst.path().join("a.txt"), b"old");
+    write_settled(&dst.path().join("gone.txt"), b"gone");
+
+    //The file at the dst path is hashed to tell Skip from Update, the one no
+    //source maps to is hashed for its Remove entry
+    let options = ScanOptions {
+        mirror: true,
+        hash_cache: HashCache::Dst,
+        ..Default::default()
+    };
+    scan_into(in_memory(), &[src.path()], dst.path(), &options);
+    let contents = fs::read_to_string(dst.path().join(".mush/hashes")).unwrap();
+    assert_eq!(contents.lines().count(), 3, "{}", contents);
+}
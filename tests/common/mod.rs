@@ -0,0 +1,62 @@
+//Each test binary uses only some of these
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use mush::{scan, Manifest, ManifestFormat, MushLink, ScanOptions};
+
+/// Write a file, creating its parent directories
+pub fn write(path: &Path, contents: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+/// Write a file modified long enough ago that its hash may be reused, from the
+/// hash cache or an earlier manifest
+pub fn write_settled(path: &Path, contents: &[u8]) {
+    write(path, contents);
+    let past = SystemTime::now() - Duration::from_secs(3600);
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(past)
+        .unwrap();
+}
+
+/// Manifest keeping its entries in memory
+pub fn in_memory() -> Manifest {
+    Manifest::Map(HashMap::new())
+}
+
+/// Manifest written to a new file of the given format at path
+pub fn to_file(path: &Path, format: ManifestFormat) -> Manifest {
+    Manifest::File(File::create(path).unwrap(), format)
+}
+
+/// Scan the source roots into dst with options, storing the entries in manifest
+pub fn scan_into(
+    mut manifest: Manifest,
+    src: &[&Path],
+    dst: &Path,
+    options: &ScanOptions,
+) -> Manifest {
+    let src = src
+        .iter()
+        .map(|path| path.to_str().unwrap().to_string())
+        .collect();
+    let dst = dst.to_str().unwrap().to_string();
+    scan(src, dst, &mut manifest, options).unwrap();
+    manifest
+}
+
+/// Entries of a manifest kept in memory, by their key
+pub fn links(manifest: Manifest) -> HashMap<String, MushLink> {
+    match manifest {
+        Manifest::Map(map) => map,
+        Manifest::File(..) => unreachable!(),
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{in_memory, scan_into};
+use mush::{export, MushMode, ScanOptions};
+use tempfile::TempDir;
+
+#[test]
+fn exported_script_applies_manifest() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::create_dir_all(src.path().join("it's here")).unwrap();
+    fs::write(src.path().join("it's here/$HOME; rm -rf x.txt"), b"odd").unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    fs::write(src.path().join("copy.txt"), b"one").unwrap();
+    fs::write(dst.path().join("stale.txt"), b"stale").unwrap();
+
+    let options = ScanOptions {
+        mirror: true,
+        ..Default::default()
+    };
+    let manifest = scan_into(in_memory(), &[src.path()], dst.path(), &options);
+
+    let script = export(&manifest, MushMode::Copy).unwrap();
+    let text = String::from_utf8_lossy(&script);
+    assert!(text.starts_with("#!/bin/sh\n"), "{}", text);
+    assert!(text.contains("# [*]"), "{}", text);
+    assert!(text.contains("'\\''"), "{}", text);
+
+    let path = work.path().join("apply.sh");
+    fs::write(&path, &script).unwrap();
+    let status = Command::new("sh").arg(&path).status().unwrap();
+    assert!(status.success());
+
+    assert_eq!(
+        fs::read(dst.path().join("it's here/$HOME; rm -rf x.txt")).unwrap(),
+        b"odd"
+    );
+    assert!(!dst.path().join("stale.txt").exists());
+    //One of the duplicates is copied and the other is only commented
+    let copied = ["one.txt", "copy.txt"]
+        .iter()
+        .filter(|name| dst.path().join(name).exists())
+        .count();
+    assert_eq!(copied, 1);
+    assert!(src.path().join("one.txt").exists());
+}
@@ -0,0 +1,312 @@
+mod common;
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+use common::{scan_into, to_file};
+use mush::{
+    push, read_manifest, rebase, seal, Manifest, ManifestFormat, MushAction, MushActionError,
+    MushLink, MushMode, MushOutcome, PushOptions, ScanOptions, UpdatePolicy, MANIFEST_VERSION,
+};
+use proptest::prelude::*;
+use tempfile::TempDir;
+
+#[test]
+fn scanned_manifest_has_header_and_metadata() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    let manifest_path = dst.path().join("manifest.mush");
+
+    let src_root = src.path().to_str().unwrap().to_string();
+    let dst_root = dst.path().join("out").to_str().unwrap().to_string();
+    let manifest = to_file(&manifest_path, ManifestFormat::Csv);
+    scan_into(
+        manifest,
+        &[src.path()],
+        &dst.path().join("out"),
+        &ScanOptions::default(),
+    );
+
+    let (header, links) =
+        read_manifest(&File::open(&manifest_path).unwrap(), ManifestFormat::Csv).unwrap();
+    assert_eq!(header.version, MANIFEST_VERSION);
+    assert_eq!(header.mush_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(header.hash, "seahash");
+    assert_eq!(header.src, vec![src_root]);
+    assert_eq!(header.dst, dst_root);
+    assert!(header.scanned > 0);
+
+    assert_eq!(links.len(), 1);
+    let meta = links[0].meta.expect("Expected metadata");
+    assert_eq!(meta.size, 3);
+    assert!(meta.mtime > 0);
+}
+
+#[test]
+fn legacy_manifest_is_migrated() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("legacy.mush");
+    fs::write(
+        &path,
+        "[+],123,/src/a.txt,/dst/a.txt\n[*],123[d1],/src/b.txt,/dst/a.txt\n",
+    )
+    .unwrap();
+
+    let (header, links) = read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).unwrap();
+    assert_eq!(header.version, 1);
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0].action, MushAction::Add);
+    assert_eq!(links[1].action, MushAction::Skip);
+    assert!(links[0].meta.is_none());
+}
+
+#[test]
+fn newer_manifest_is_refused() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("future.mush");
+    let contents = format!(
+        "#mush-manifest {}\n[+],123,/src/a.txt,/dst/a.txt,,1,1,644\n",
+        MANIFEST_VERSION + 1
+    );
+    fs::write(&path, contents).unwrap();
+
+    assert!(read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).is_err());
+}
+
+#[test]
+fn awkward_file_names_survive_the_manifest() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let name = OsString::from_vec(b"a,b\nc%41\xff.txt".to_vec());
+    fs::write(src.path().join(&name), b"one").unwrap();
+    let manifest_path = dst.path().join("manifest.mush");
+
+    let manifest = to_file(&manifest_path, ManifestFormat::Csv);
+    scan_into(
+        manifest,
+        &[src.path()],
+        &dst.path().join("out"),
+        &ScanOptions::default(),
+    );
+
+    let (_, links) =
+        read_manifest(&File::open(&manifest_path).unwrap(), ManifestFormat::Csv).unwrap();
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].src, src.path().join(&name));
+    assert_eq!(links[0].dst, dst.path().join("out").join(&name));
+}
+
+/// Any path a unix filesystem accepts, which is any bytes other than NUL
+fn any_path() -> impl Strategy<Value = PathBuf> {
+    proptest::collection::vec(1u8..=255, 1..64)
+        .prop_map(|bytes| PathBuf::from(OsString::from_vec(bytes)))
+}
+
+proptest! {
+    #[test]
+    fn paths_round_trip(src in any_path(), dst in any_path()) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("random.mush");
+        let link = MushLink::new(MushAction::Add, String::from("123"), src.to_owned(), dst.to_owned());
+        writeln!(File::create(&path).unwrap(), "{}", link).unwrap();
+
+        let (_, links) = read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).unwrap();
+        prop_assert_eq!(links.len(), 1);
+        prop_assert_eq!(&links[0].src, &src);
+        prop_assert_eq!(&links[0].dst, &dst);
+    }
+}
+
+/// Scan one file into a manifest of the given format and read it back
+fn scan_as(format: ManifestFormat, name: &str) -> (TempDir, String, Vec<MushLink>) {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::write(src.path().join("a,b.txt"), b"one").unwrap();
+    let manifest_path = dst.path().join(name);
+    assert_eq!(ManifestFormat::from_path(&manifest_path), format);
+
+    let manifest = to_file(&manifest_path, format);
+    scan_into(
+        manifest,
+        &[src.path()],
+        &dst.path().join("out"),
+        &ScanOptions::default(),
+    );
+
+    let contents = fs::read_to_string(&manifest_path).unwrap();
+    let (header, links) = read_manifest(&File::open(&manifest_path).unwrap(), format).unwrap();
+    assert_eq!(header.version, MANIFEST_VERSION);
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].action, MushAction::Add);
+    assert_eq!(links[0].src, src.path().join("a,b.txt"));
+    assert_eq!(links[0].meta.unwrap().size, 3);
+    (dst, contents, links)
+}
+
+#[test]
+fn ndjson_manifest_has_named_fields() {
+    let (_dst, contents, _) = scan_as(ManifestFormat::Ndjson, "manifest.jsonl");
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[2].starts_with(r#"{"trailer":{"entries":1,"#));
+    assert!(lines[1].contains(r#""action":"add""#));
+    assert!(lines[1].contains(r#""size":3"#));
+    assert!(lines[1].contains(r#""reason":"not at the destination yet""#));
+}
+
+#[test]
+fn json_manifest_round_trips() {
+    let (_dst, contents, _) = scan_as(ManifestFormat::Json, "manifest.json");
+    assert!(contents.contains(r#""links": ["#));
+}
+
+#[test]
+fn toml_manifest_round_trips() {
+    let (_dst, contents, _) = scan_as(ManifestFormat::Toml, "manifest.toml");
+    assert!(contents.contains("[[links]]"));
+}
+
+/// Scan src into a CSV manifest at path and return its text
+fn scan_to_csv(src: &Path, dst: &Path, path: &Path) -> String {
+    let manifest = to_file(path, ManifestFormat::Csv);
+    scan_into(manifest, &[src], dst, &ScanOptions::default());
+    fs::read_to_string(path).unwrap()
+}
+
+fn push_csv(path: &Path) -> Result<Vec<(MushLink, MushOutcome)>, MushActionError> {
+    let manifest = Manifest::File(File::open(path).unwrap(), ManifestFormat::Csv);
+    let options = PushOptions {
+        mode: MushMode::Copy,
+        update: UpdatePolicy::Always,
+        trash: None,
+    };
+    push(&manifest, &options)
+}
+
+#[test]
+fn edited_manifest_is_honoured() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("keep.txt"), b"keep").unwrap();
+    fs::write(src.path().join("drop.txt"), b"drop").unwrap();
+    let path = work.path().join("manifest.mush");
+    let contents = scan_to_csv(src.path(), dst.path(), &path);
+    let edited: Vec<String> = contents
+        .lines()
+        .map(|line| match line {
+            l if l.contains("drop.txt") => l.replacen("[+]", "[_]", 1),
+            l => l.replace("dst:keep.txt,", "dst:renamed.txt,"),
+        })
+        .collect();
+    fs::write(&path, edited.join("\n")).unwrap();
+    assert!(push_csv(&path).is_err());
+
+    seal(&path, ManifestFormat::Csv, None).unwrap();
+    push_csv(&path).unwrap();
+    assert!(dst.path().join("renamed.txt").exists());
+    assert!(!dst.path().join("keep.txt").exists());
+    assert!(!dst.path().join("drop.txt").exists());
+}
+
+#[test]
+fn edited_dst_outside_root_is_refused() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    let path = work.path().join("manifest.mush");
+    let contents = scan_to_csv(src.path(), dst.path(), &path);
+
+    let escaped = contents.replace("dst:one.txt,", "dst:../escaped.txt,");
+    fs::write(&path, escaped).unwrap();
+
+    let e = push_csv(&path).unwrap_err();
+    assert!(e.message.contains("line 7"), "{}", e.message);
+    assert!(e.message.contains("outside"), "{}", e.message);
+    assert!(!dst.path().parent().unwrap().join("escaped.txt").exists());
+}
+
+#[test]
+fn bad_lines_are_reported_by_number() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("broken.mush");
+    let contents = format!(
+        "#mush-manifest {}\n[+],123,/src/a.txt,/dst/a.txt,,1,1,644\n[~],123,/src/b.txt,/dst/b.txt\n[+],123\n",
+        MANIFEST_VERSION
+    );
+    fs::write(&path, contents).unwrap();
+
+    let e = read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).unwrap_err();
+    assert!(
+        e.message.contains("line 3: unknown action [~]"),
+        "{}",
+        e.message
+    );
+    assert!(e.message.contains("line 4: missing src"), "{}", e.message);
+}
+
+#[test]
+fn manifest_paths_are_relative_to_named_roots() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    let path = work.path().join("manifest.mush");
+    let contents = scan_to_csv(src.path(), dst.path(), &path);
+
+    assert!(contents.contains(&format!("#src src0 {}", src.path().display())));
+    assert!(
+        contents.contains(",src0:one.txt,dst:one.txt,"),
+        "{}",
+        contents
+    );
+
+    let (_, links) = read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).unwrap();
+    assert_eq!(links[0].src, src.path().join("one.txt"));
+    assert_eq!(links[0].dst, dst.path().join("one.txt"));
+}
+
+#[test]
+fn rebased_manifest_runs_against_new_roots() {
+    let planned = TempDir::new().unwrap();
+    let moved = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    let src = planned.path().join("photos");
+    fs::create_dir_all(src.join("2024")).unwrap();
+    fs::write(src.join("2024/one.jpg"), b"one").unwrap();
+    let path = work.path().join("manifest.mush");
+    scan_to_csv(&src, &planned.path().join("backup"), &path);
+
+    //Another machine mounts the same drive elsewhere
+    let new_src = moved.path().join("photos");
+    fs::rename(&src, &new_src).unwrap();
+    let new_dst = moved.path().join("backup");
+
+    let (header, links) = read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).unwrap();
+    let rebase_src = vec![format!("src0={}", new_src.display())];
+    let (header, links) = rebase(&header, links, &rebase_src, new_dst.to_str()).unwrap();
+    assert_eq!(Path::new(&header.dst), new_dst);
+    assert_eq!(links[0].src, new_src.join("2024/one.jpg"));
+    assert_eq!(links[0].dst, new_dst.join("2024/one.jpg"));
+
+    assert!(rebase(&header, links.clone(), &[String::from("src3=/x")], None).is_err());
+    let manifest = Manifest::Map(
+        links
+            .into_iter()
+            .enumerate()
+            .map(|(i, link)| (i.to_string(), link))
+            .collect(),
+    );
+    let options = PushOptions {
+        mode: MushMode::Copy,
+        update: UpdatePolicy::Always,
+        trash: None,
+    };
+    push(&manifest, &options).unwrap();
+    assert!(new_dst.join("2024/one.jpg").exists());
+}
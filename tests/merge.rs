@@ -0,0 +1,143 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+
+use common::{scan_into, to_file};
+use mush::{
+    diff, merge, read_manifest, EntryChange, Manifest, ManifestFormat, ManifestHeader, MushAction,
+    MushLink, ScanOptions,
+};
+use tempfile::TempDir;
+
+/// Scan src into a CSV manifest at path and read it back
+fn scan_to(src: &Path, dst: &Path, path: &Path) -> (ManifestHeader, Vec<MushLink>) {
+    let manifest = to_file(path, ManifestFormat::Csv);
+    scan_into(manifest, &[src], dst, &ScanOptions::default());
+    read_manifest(&File::open(path).unwrap(), ManifestFormat::Csv).unwrap()
+}
+
+fn open(path: &Path) -> Manifest {
+    Manifest::File(File::open(path).unwrap(), ManifestFormat::Csv)
+}
+
+#[test]
+fn merge_dedups_across_manifests() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(first.path().join("photo.jpg"), b"first photo").unwrap();
+    fs::write(first.path().join("same.txt"), b"same").unwrap();
+    fs::write(second.path().join("photo.jpg"), b"second photo").unwrap();
+    fs::write(second.path().join("copy.txt"), b"same").unwrap();
+
+    let inputs = vec![
+        scan_to(first.path(), dst.path(), &work.path().join("first.mush")),
+        scan_to(second.path(), dst.path(), &work.path().join("second.mush")),
+    ];
+    let mut manifest = Manifest::Map(HashMap::new());
+    merge(inputs, None, &mut manifest, &ScanOptions::default()).unwrap();
+    let links: Vec<MushLink> = match manifest {
+        Manifest::Map(map) => map.into_values().collect(),
+        Manifest::File(..) => unreachable!(),
+    };
+    let link_for = |src: &Path| links.iter().find(|l| l.src == src).unwrap();
+
+    assert_eq!(links.len(), 4);
+    let copy = link_for(&second.path().join("copy.txt"));
+    assert_eq!(copy.action, MushAction::Skip);
+    assert_eq!(copy.dst, dst.path().join("same.txt"));
+    let renamed = link_for(&second.path().join("photo.jpg"));
+    assert_eq!(renamed.action, MushAction::Add);
+    assert_eq!(renamed.dst, dst.path().join("photo_1.jpg"));
+}
+
+#[test]
+fn merge_keeps_ignored_entries() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(first.path().join("draft.txt"), b"draft").unwrap();
+    fs::write(second.path().join("copy.txt"), b"draft").unwrap();
+
+    let (header, mut links) = scan_to(first.path(), dst.path(), &work.path().join("first.mush"));
+    links[0].action = MushAction::Ignore;
+    let inputs = vec![
+        (header, links),
+        scan_to(second.path(), dst.path(), &work.path().join("second.mush")),
+    ];
+    let mut manifest = Manifest::Map(HashMap::new());
+    merge(inputs, None, &mut manifest, &ScanOptions::default()).unwrap();
+    let links: Vec<MushLink> = match manifest {
+        Manifest::Map(map) => map.into_values().collect(),
+        Manifest::File(..) => unreachable!(),
+    };
+    let link_for = |src: &Path| links.iter().find(|l| l.src == src).unwrap();
+
+    assert_eq!(links.len(), 2);
+    let draft = link_for(&first.path().join("draft.txt"));
+    assert_eq!(draft.action, MushAction::Ignore);
+    let copy = link_for(&second.path().join("copy.txt"));
+    assert_eq!(copy.action, MushAction::Add);
+    assert_eq!(copy.dst, dst.path().join("copy.txt"));
+}
+
+#[test]
+fn merge_needs_a_shared_destination() {
+    let src = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    let inputs = vec![
+        scan_to(
+            src.path(),
+            &work.path().join("a"),
+            &work.path().join("a.mush"),
+        ),
+        scan_to(
+            src.path(),
+            &work.path().join("b"),
+            &work.path().join("b.mush"),
+        ),
+    ];
+
+    let mut manifest = Manifest::Map(HashMap::new());
+    assert!(merge(inputs.clone(), None, &mut manifest, &ScanOptions::default()).is_err());
+
+    let dst = work.path().join("c").to_str().unwrap().to_string();
+    merge(inputs, Some(dst), &mut manifest, &ScanOptions::default()).unwrap();
+    match manifest {
+        Manifest::Map(map) => assert_eq!(map.len(), 1),
+        Manifest::File(..) => unreachable!(),
+    }
+}
+
+#[test]
+fn diff_shows_changed_and_new_entries() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("same.txt"), b"same").unwrap();
+    fs::write(src.path().join("edited.txt"), b"before").unwrap();
+    let before = work.path().join("before.mush");
+    scan_to(src.path(), dst.path(), &before);
+
+    fs::write(src.path().join("edited.txt"), b"after").unwrap();
+    fs::write(src.path().join("new.txt"), b"new").unwrap();
+    let after = work.path().join("after.mush");
+    scan_to(src.path(), dst.path(), &after);
+
+    let changed = diff(&open(&before), &open(&after)).unwrap();
+    assert_eq!(changed.changes.len(), 2);
+    assert!(changed.changes.iter().any(|c| matches!(
+        c,
+        EntryChange::Changed(a, b) if a.src.ends_with("edited.txt") && a.hash != b.hash
+    )));
+    assert!(changed
+        .changes
+        .iter()
+        .any(|c| matches!(c, EntryChange::Added(b) if b.src.ends_with("new.txt"))));
+    assert!(diff(&open(&after), &open(&after)).unwrap().is_empty());
+}
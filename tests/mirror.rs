@@ -0,0 +1,90 @@
+mod common;
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use common::{in_memory, scan_into};
+use mush::{
+    push, Manifest, MushAction, MushLink, MushMode, MushOutcome, PushOptions, ScanOptions,
+    UpdatePolicy,
+};
+use tempfile::TempDir;
+
+fn scan_mirror(src: &Path, dst: &Path) -> Manifest {
+    let options = ScanOptions {
+        mirror: true,
+        ..Default::default()
+    };
+    scan_into(in_memory(), &[src], dst, &options)
+}
+
+fn removals(manifest: &Manifest) -> Vec<MushLink> {
+    match manifest {
+        Manifest::Map(map) => map
+            .values()
+            .filter(|link| link.action == MushAction::Remove)
+            .cloned()
+            .collect(),
+        Manifest::File(..) => unreachable!(),
+    }
+}
+
+fn push_with(manifest: &Manifest, trash: Option<PathBuf>) -> Vec<(MushLink, MushOutcome)> {
+    let options = PushOptions {
+        mode: MushMode::Copy,
+        update: UpdatePolicy::Always,
+        trash,
+    };
+    push(manifest, &options).unwrap()
+}
+
+#[test]
+fn mirror_removes_dst_only_files_and_keeps_mush_state() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::write(src.path().join("kept.txt"), "kept").unwrap();
+    fs::write(dst.path().join("kept.txt"), "kept").unwrap();
+    fs::write(dst.path().join("stale.txt"), "stale").unwrap();
+    fs::create_dir(dst.path().join(".mush")).unwrap();
+    fs::write(dst.path().join(".mush/hashes"), "").unwrap();
+
+    let manifest = scan_mirror(src.path(), dst.path());
+    let removals = removals(&manifest);
+    assert_eq!(removals.len(), 1);
+    assert_eq!(removals[0].dst, dst.path().join("stale.txt"));
+    assert_eq!(removals[0].target(), dst.path().join("stale.txt"));
+
+    push_with(&manifest, None);
+    assert!(!dst.path().join("stale.txt").exists());
+    assert!(dst.path().join("kept.txt").exists());
+    assert!(dst.path().join(".mush/hashes").exists());
+}
+
+#[test]
+fn trash_keeps_the_full_path_of_removed_files() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let trash = TempDir::new().unwrap();
+    fs::write(src.path().join("kept.txt"), "kept").unwrap();
+    fs::create_dir(dst.path().join("old")).unwrap();
+    let stale = dst.path().join("old/stale.txt");
+    fs::write(&stale, "stale").unwrap();
+
+    let manifest = scan_mirror(src.path(), dst.path());
+    let outcomes = push_with(&manifest, Some(trash.path().to_path_buf()));
+    let trashed: Vec<&MushLink> = outcomes
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, MushOutcome::Trashed))
+        .map(|(link, _)| link)
+        .collect();
+    assert_eq!(trashed.len(), 1);
+    assert!(!stale.exists());
+
+    //Every component of the removed path is kept beneath the trash folder
+    let relative: PathBuf = stale
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let moved = trash.path().join(relative);
+    assert_eq!(fs::read_to_string(moved).unwrap(), "stale");
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{in_memory, scan_into};
+use mush::{plan, Manifest, MushAction, ScanOptions};
+use tempfile::TempDir;
+
+fn scan_manifest(src: &TempDir, dst: &Path) -> Manifest {
+    scan_into(in_memory(), &[src.path()], dst, &ScanOptions::default())
+}
+
+#[test]
+fn plan_counts_actions_and_new_dirs() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::create_dir_all(src.path().join("nested")).unwrap();
+    fs::write(src.path().join("nested/one.txt"), b"one").unwrap();
+    fs::write(src.path().join("nested/copy.txt"), b"one").unwrap();
+    fs::write(src.path().join("three.txt"), b"three").unwrap();
+
+    let target = dst.path().join("new");
+    let manifest = scan_manifest(&src, &target);
+    let plan = plan(&manifest).unwrap();
+
+    assert!(plan.has_changes());
+    assert_eq!(plan.totals[&MushAction::Add], (2, 8));
+    assert_eq!(plan.totals[&MushAction::Skip], (1, 3));
+    assert!(plan.new_dirs.contains(&target));
+    assert!(plan.new_dirs.contains(&target.join("nested")));
+    assert!(!target.exists());
+}
+
+#[test]
+fn plan_without_changes() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    fs::write(dst.path().join("one.txt"), b"one").unwrap();
+
+    let manifest = scan_manifest(&src, dst.path());
+    let plan = plan(&manifest).unwrap();
+
+    assert!(!plan.has_changes());
+    assert!(plan.new_dirs.is_empty());
+}
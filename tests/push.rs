@@ -0,0 +1,182 @@
+mod common;
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use common::{in_memory, links, scan_into, write};
+use mush::{
+    push, Manifest, MushAction, MushLink, MushMode, MushOutcome, PushOptions, ScanOptions,
+    UpdatePolicy,
+};
+use tempfile::TempDir;
+
+fn push_as(
+    manifest: &Manifest,
+    mode: MushMode,
+    update: UpdatePolicy,
+) -> Vec<(MushLink, MushOutcome)> {
+    let options = PushOptions {
+        mode,
+        update,
+        trash: None,
+    };
+    push(manifest, &options).unwrap()
+}
+
+/// Set the modified time of path to age seconds ago
+fn age(path: &Path, age: u64) {
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(age))
+        .unwrap();
+}
+
+/// Scan a source file over a different file at its dst, the source modified
+/// src_age seconds ago and the dst file dst_age seconds ago, then push it
+/// under policy and return the outcome and what dst holds afterwards
+fn update_with(policy: UpdatePolicy, src_age: u64, dst_age: u64) -> (MushOutcome, String) {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let (from, to) = (src.path().join("notes.txt"), dst.path().join("notes.txt"));
+    write(&from, b"source");
+    write(&to, b"destination");
+    age(&from, src_age);
+    age(&to, dst_age);
+
+    let manifest = scan_into(
+        in_memory(),
+        &[src.path()],
+        dst.path(),
+        &ScanOptions::default(),
+    );
+    let mut outcomes = push_as(&manifest, MushMode::Copy, policy);
+    assert_eq!(outcomes.len(), 1);
+    let (link, outcome) = outcomes.remove(0);
+    assert_eq!(link.action, MushAction::Update);
+    (outcome, fs::read_to_string(&to).unwrap())
+}
+
+#[test]
+fn changed_dst_file_is_scanned_as_update() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("notes.txt"), b"source");
+    write(&dst.path().join("notes.txt"), b"destination");
+    write(&src.path().join("same.txt"), b"same");
+    write(&dst.path().join("same.txt"), b"same");
+
+    let manifest = scan_into(
+        in_memory(),
+        &[src.path()],
+        dst.path(),
+        &ScanOptions::default(),
+    );
+    let links = links(manifest);
+    let changed = links
+        .values()
+        .find(|link| link.dst == dst.path().join("notes.txt"))
+        .unwrap();
+    assert_eq!(changed.action, MushAction::Update);
+    let dst_hash = changed.dst_hash.as_ref().unwrap();
+    assert_ne!(dst_hash, &changed.hash);
+    let same = links
+        .values()
+        .find(|link| link.dst == dst.path().join("same.txt"))
+        .unwrap();
+    assert_eq!(same.action, MushAction::Skip);
+    assert_eq!(same.dst_hash, None);
+}
+
+#[test]
+fn always_replaces_an_older_or_newer_dst() {
+    let (outcome, contents) = update_with(UpdatePolicy::Always, 60, 3600);
+    assert!(matches!(outcome, MushOutcome::Copied));
+    assert_eq!(contents, "source");
+
+    let (outcome, contents) = update_with(UpdatePolicy::Always, 3600, 60);
+    assert!(matches!(outcome, MushOutcome::Copied));
+    assert_eq!(contents, "source");
+}
+
+#[test]
+fn newer_replaces_only_an_older_dst() {
+    let (outcome, contents) = update_with(UpdatePolicy::Newer, 60, 3600);
+    assert!(matches!(outcome, MushOutcome::Copied));
+    assert_eq!(contents, "source");
+
+    let (outcome, contents) = update_with(UpdatePolicy::Newer, 3600, 60);
+    assert!(matches!(outcome, MushOutcome::Skipped));
+    assert_eq!(contents, "destination");
+}
+
+#[test]
+fn never_keeps_an_older_or_newer_dst() {
+    let (outcome, contents) = update_with(UpdatePolicy::Never, 60, 3600);
+    assert!(matches!(outcome, MushOutcome::Skipped));
+    assert_eq!(contents, "destination");
+
+    let (outcome, contents) = update_with(UpdatePolicy::Never, 3600, 60);
+    assert!(matches!(outcome, MushOutcome::Skipped));
+    assert_eq!(contents, "destination");
+}
+
+#[test]
+fn pull_skips_files_the_dst_already_holds() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("2024/photo.jpg"), b"photo");
+    write(&src.path().join("notes.txt"), b"notes");
+    write(&dst.path().join("sorted/photo.jpg"), b"photo");
+
+    //Pull indexes the current directory so copies kept anywhere in it are not written again
+    let options = ScanOptions {
+        index_dst: true,
+        ..Default::default()
+    };
+    let manifest = scan_into(in_memory(), &[src.path()], dst.path(), &options);
+    let outcomes = push_as(&manifest, MushMode::Copy, UpdatePolicy::Newer);
+    assert_eq!(outcomes.len(), 2);
+    for (link, outcome) in &outcomes {
+        match link.src.ends_with("photo.jpg") {
+            true => {
+                assert_eq!(link.action, MushAction::Skip);
+                assert!(matches!(outcome, MushOutcome::Skipped));
+            }
+            false => {
+                assert_eq!(link.action, MushAction::Add);
+                assert!(matches!(outcome, MushOutcome::Copied));
+            }
+        }
+    }
+    assert!(!dst.path().join("2024/photo.jpg").exists());
+    assert_eq!(fs::read(dst.path().join("notes.txt")).unwrap(), b"notes");
+    assert_eq!(
+        fs::read(src.path().join("2024/photo.jpg")).unwrap(),
+        b"photo"
+    );
+}
+
+#[test]
+fn move_mode_leaves_nothing_at_the_source() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("2024/photo.jpg"), b"photo");
+
+    let manifest = scan_into(
+        in_memory(),
+        &[src.path()],
+        dst.path(),
+        &ScanOptions::default(),
+    );
+    let outcomes = push_as(&manifest, MushMode::Move, UpdatePolicy::Always);
+    assert_eq!(outcomes.len(), 1);
+    assert!(matches!(outcomes[0].1, MushOutcome::Moved));
+    assert!(!src.path().join("2024/photo.jpg").exists());
+    assert_eq!(
+        fs::read(dst.path().join("2024/photo.jpg")).unwrap(),
+        b"photo"
+    );
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::fs;
+
+use common::{in_memory, scan_into};
+use mush::{query, render, stats, Manifest, MushAction, Query, QueryOutput, ScanOptions};
+use tempfile::TempDir;
+
+/// Scan a source holding a 4 byte file copied three times and a 2 byte file
+fn scan_sample() -> (TempDir, TempDir, Manifest) {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::create_dir_all(src.path().join("photos")).unwrap();
+    fs::write(src.path().join("photos/a.jpg"), b"same").unwrap();
+    fs::write(src.path().join("photos/b.jpg"), b"same").unwrap();
+    fs::write(src.path().join("photos/c.jpg"), b"same").unwrap();
+    fs::write(src.path().join("notes.txt"), b"hi").unwrap();
+
+    let manifest = scan_into(
+        in_memory(),
+        &[src.path()],
+        dst.path(),
+        &ScanOptions::default(),
+    );
+    (src, dst, manifest)
+}
+
+#[test]
+fn stats_count_copies_and_savings() {
+    let (_src, _dst, manifest) = scan_sample();
+    let stats = stats(&manifest, 10).unwrap();
+
+    assert_eq!(stats.totals[&MushAction::Add], (2, 6));
+    assert_eq!(stats.totals[&MushAction::Skip], (2, 8));
+    assert_eq!(stats.bytes_to_copy, 6);
+    assert_eq!(stats.bytes_saved, 8);
+    assert_eq!(stats.top_duplicates.len(), 1);
+    assert_eq!(stats.top_duplicates[0].files, 3);
+    assert_eq!(stats.top_duplicates[0].saved, 8);
+}
+
+#[test]
+fn query_filters_by_action_glob_and_size() {
+    let (src, _dst, manifest) = scan_sample();
+
+    let skipped = Query {
+        actions: vec![MushAction::Skip],
+        ..Default::default()
+    };
+    assert_eq!(query(&manifest, &skipped).unwrap().len(), 2);
+
+    let photos = Query {
+        glob: Some(format!("{}/photos/*", src.path().display())),
+        ..Default::default()
+    };
+    assert_eq!(query(&manifest, &photos).unwrap().len(), 3);
+
+    let small = Query {
+        max_size: Some(3),
+        ..Default::default()
+    };
+    let links = query(&manifest, &small).unwrap();
+    assert_eq!(links.len(), 1);
+    assert!(links[0].src.ends_with("notes.txt"));
+
+    let json = render(&links, QueryOutput::Json);
+    assert!(json.contains(r#""action": "add""#), "{}", json);
+    assert!(render(&links, QueryOutput::Table).contains("notes.txt"));
+}
@@ -0,0 +1,631 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+use common::{in_memory, links, scan_into, write, write_settled};
+use mush::{
+    scan, source_changes, CollisionPolicy, HashCache, HashType, ManifestHeader, MushAction,
+    MushLink, PathConflictPolicy, ScanOptions,
+};
+use tempfile::TempDir;
+
+/// Seeds used by `SeaHasher::default()`
+const SEEDS: [u64; 4] = [
+    0x16f11fe89b0d677c,
+    0xb480a793d8e6c86c,
+    0x6fe2e5aaf078ebc9,
+    0x14f994a4c5259381,
+];
+
+fn diffuse(mut x: u64) -> u64 {
+    x = x.wrapping_mul(0x6eed0e9da4d94a4f);
+    x ^= (x >> 32) >> (x >> 60);
+    x.wrapping_mul(0x6eed0e9da4d94a4f)
+}
+
+fn undiffuse(mut x: u64) -> u64 {
+    x = x.wrapping_mul(0x2f72b4215a3d8caf);
+    x ^= (x >> 32) >> (x >> 60);
+    x.wrapping_mul(0x2f72b4215a3d8caf)
+}
+
+fn seahash(bytes: &[u8]) -> u64 {
+    let mut hasher = seahash::SeaHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Build two different 32 byte files with the same seahash.
+///
+/// A 32 byte input feeds one word into each of the four lanes and the lanes
+/// are xored together before the final diffuse, so changing the first word
+/// and solving for the second keeps the xor of the lanes and the hash intact.
+fn forge_collision() -> (Vec<u8>, Vec<u8>) {
+    let words: [u64; 4] = [
+        0x6d7573682d6f7269,
+        0x67696e616c2d6669,
+        0x6c652d636f6e7465,
+        0x6e74732d2d2d2d0a,
+    ];
+    let mut forged = words;
+    forged[0] ^= 0x0101010101010101;
+
+    let lane = |i: usize, w: u64| diffuse(SEEDS[i] ^ w);
+    let target = lane(0, words[0]) ^ lane(1, words[1]) ^ lane(0, forged[0]);
+    forged[1] = undiffuse(target) ^ SEEDS[1];
+
+    let to_bytes = |words: [u64; 4]| {
+        words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<u8>>()
+    };
+    let (original, forged) = (to_bytes(words), to_bytes(forged));
+    assert_ne!(original, forged);
+    assert_eq!(seahash(&original), seahash(&forged));
+    (original, forged)
+}
+
+fn scan_links(src: &[&Path], dst: &Path) -> HashMap<String, MushLink> {
+    scan_links_with(src, dst, &ScanOptions::default())
+}
+
+fn scan_links_with(src: &[&Path], dst: &Path, options: &ScanOptions) -> HashMap<String, MushLink> {
+    links(scan_into(in_memory(), src, dst, options))
+}
+
+fn link_for<'a>(links: &'a HashMap<String, MushLink>, src: &Path) -> &'a MushLink {
+    links
+        .values()
+        .find(|l| Path::new(&l.src) == src)
+        .expect("Expected a link for src")
+}
+
+#[test]
+fn identical_files_are_skipped() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let first = src.path().join("a/photo.jpg");
+    let second = src.path().join("b/copy.jpg");
+    write(&first, b"same contents");
+    write(&second, b"same contents");
+
+    let links = scan_links(&[src.path()], dst.path());
+
+    assert_eq!(links.len(), 2);
+    let actions: Vec<&MushAction> = links.values().map(|l| &l.action).collect();
+    assert!(actions.contains(&&MushAction::Add));
+    assert!(actions.contains(&&MushAction::Skip));
+    let added = links
+        .values()
+        .find(|l| l.action == MushAction::Add)
+        .unwrap();
+    let skipped = links
+        .values()
+        .find(|l| l.action == MushAction::Skip)
+        .unwrap();
+    assert_eq!(skipped.dst, added.dst);
+}
+
+#[test]
+fn identical_files_across_sources_are_skipped() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&first.path().join("photo.jpg"), b"same contents");
+    write(&second.path().join("nested/photo.jpg"), b"same contents");
+
+    let links = scan_links(&[first.path(), second.path()], dst.path());
+
+    let added = link_for(&links, &first.path().join("photo.jpg"));
+    let skipped = link_for(&links, &second.path().join("nested/photo.jpg"));
+    assert_eq!(added.action, MushAction::Add);
+    assert_eq!(skipped.action, MushAction::Skip);
+    assert_eq!(skipped.dst, added.dst);
+}
+
+#[test]
+fn forged_hash_collision_is_detected() {
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let first = src.path().join("original.bin");
+    let second = src.path().join("sub/forged.bin");
+    write(&first, &original);
+    write(&second, &forged);
+
+    let links = scan_links(&[src.path()], dst.path());
+
+    //Whichever file is walked first is added and the other flagged
+    let mut actions = vec![
+        link_for(&links, &first).action.to_string(),
+        link_for(&links, &second).action.to_string(),
+    ];
+    actions.sort();
+    assert_eq!(links.len(), 2);
+    assert_eq!(actions, vec!["[!]", "[+]"]);
+}
+
+#[test]
+fn cryptographic_hash_sees_through_forged_collision() {
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let first = src.path().join("original.bin");
+    let second = src.path().join("sub/forged.bin");
+    write(&first, &original);
+    write(&second, &forged);
+
+    for (hash, digest_len) in [(HashType::Sha256, 64), (HashType::Blake3, 64)] {
+        let options = ScanOptions {
+            hash,
+            ..Default::default()
+        };
+        let links = scan_links_with(&[src.path()], dst.path(), &options);
+        for path in [&first, &second] {
+            let link = link_for(&links, path);
+            assert_eq!(link.action, MushAction::Add);
+            assert_eq!(link.hash.len(), digest_len);
+        }
+    }
+}
+
+#[test]
+fn large_files_are_prefiltered_by_size_and_ends() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let big = |fill: u8| vec![fill; 64 * 1024];
+    let mut middle = big(3);
+    middle[32 * 1024] = 4;
+    write(&src.path().join("unique.bin"), &vec![1; 50_000]);
+    write(&src.path().join("a.bin"), &big(2));
+    write(&src.path().join("b.bin"), &big(2));
+    write(&src.path().join("c.bin"), &big(3));
+    write(&src.path().join("d.bin"), &middle);
+    write(&src.path().join("e.bin"), &big(5));
+
+    let links = scan_links(&[src.path()], dst.path());
+    let link = |name: &str| link_for(&links, &src.path().join(name));
+
+    //Alone in their size or among the ends of same sized files
+    assert!(link("unique.bin").hash.starts_with("partial:"));
+    assert!(link("e.bin").hash.starts_with("partial:"));
+    //Same size and ends, only a full hash tells them apart
+    assert!(!link("a.bin").hash.starts_with("partial:"));
+    assert_eq!(link("c.bin").action, MushAction::Add);
+    assert_eq!(link("d.bin").action, MushAction::Add);
+    assert_ne!(link("c.bin").hash, link("d.bin").hash);
+    let mut actions = vec![link("a.bin").action.clone(), link("b.bin").action.clone()];
+    actions.sort();
+    assert_eq!(actions, vec![MushAction::Add, MushAction::Skip]);
+}
+
+/// Number of hashes of kind kept in the hash cache of dst
+fn cached(dst: &Path, kind: &str) -> usize {
+    fs::read_to_string(dst.join(".mush/hashes"))
+        .unwrap()
+        .lines()
+        .filter(|line| line.split(' ').nth(5) == Some(kind))
+        .count()
+}
+
+#[test]
+fn file_alone_in_its_size_is_never_read_in_full() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write_settled(&src.path().join("video.bin"), &vec![1; 64 * 1024]);
+    write_settled(&src.path().join("a.txt"), b"same");
+    write_settled(&src.path().join("b.txt"), b"same");
+
+    let options = ScanOptions {
+        hash_cache: HashCache::Dst,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    let link = link_for(&links, &src.path().join("video.bin"));
+    assert!(link.hash.starts_with("partial:"));
+
+    //The cache shows what was read: only the two small files sharing a size
+    //were hashed in full
+    assert_eq!(cached(dst.path(), "partial:seahash"), 1);
+    assert_eq!(cached(dst.path(), "seahash"), 2);
+}
+
+#[test]
+fn full_digests_key_every_file_by_its_whole_content() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("unique.bin"), &vec![1; 50_000]);
+
+    let options = ScanOptions {
+        full_digests: true,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    assert_eq!(
+        link_for(&links, &src.path().join("unique.bin")).hash,
+        seahash(&vec![1; 50_000]).to_string()
+    );
+}
+
+#[test]
+fn large_file_unique_in_sources_matches_indexed_dst() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("video.bin"), &vec![7; 64 * 1024]);
+    write(&dst.path().join("old/video.bin"), &vec![7; 64 * 1024]);
+    write(&dst.path().join("video.bin"), &vec![8; 64 * 1024]);
+
+    let options = ScanOptions {
+        index_dst: true,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    let link = link_for(&links, &src.path().join("video.bin"));
+    assert_eq!(link.action, MushAction::Skip);
+    assert_eq!(link.dst, dst.path().join("old/video.bin"));
+}
+
+#[test]
+fn only_dst_files_like_a_source_file_are_hashed() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let big = |fill: u8| vec![fill; 64 * 1024];
+    let mut middle = big(1);
+    middle[32 * 1024] = 2;
+    write_settled(&src.path().join("video.bin"), &big(1));
+    write_settled(&dst.path().join("same.bin"), &big(1));
+    write_settled(&dst.path().join("middle.bin"), &middle);
+    write_settled(&dst.path().join("ends.bin"), &big(2));
+    write_settled(&dst.path().join("size.bin"), &vec![1; 50_000]);
+
+    let options = ScanOptions {
+        index_dst: true,
+        hash_cache: HashCache::Dst,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    let link = link_for(&links, &src.path().join("video.bin"));
+    assert_eq!(link.action, MushAction::Skip);
+    assert_eq!(link.dst, dst.path().join("same.bin"));
+
+    //The cache shows what was read: every file of the same size had its ends
+    //hashed, only those with the same ends were hashed in full
+    assert_eq!(cached(dst.path(), "partial:seahash"), 4);
+    assert_eq!(cached(dst.path(), "seahash"), 3);
+}
+
+#[test]
+fn parallel_scans_make_the_same_manifest() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    for dir in 0..8 {
+        for file in 0..8 {
+            let path = src.path().join(format!("{}/{}/copy.txt", dir, file));
+            write(&path, format!("{}", (dir * 8 + file) % 5).as_bytes());
+        }
+    }
+    write(&src.path().join("big/a.bin"), &vec![1; 64 * 1024]);
+    write(&src.path().join("big/b.bin"), &vec![1; 64 * 1024]);
+
+    let describe = |threads: usize| {
+        let options = ScanOptions {
+            threads,
+            ..Default::default()
+        };
+        let mut links: Vec<String> = scan_links_with(&[src.path()], dst.path(), &options)
+            .into_values()
+            .map(|link| link.to_string())
+            .collect();
+        links.sort();
+        links
+    };
+    let single = describe(1);
+    assert_eq!(single.len(), 66);
+    for _ in 0..4 {
+        assert_eq!(describe(8), single);
+    }
+}
+
+#[test]
+fn scan_since_last_matches_a_full_scan() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let path = |name: &str| src.path().join(name);
+    write_settled(&path("keep.txt"), b"keep");
+    write_settled(&path("edit.txt"), b"edit");
+    write_settled(&path("gone.txt"), b"gone");
+    write_settled(&path("old_name.txt"), b"moved");
+    write_settled(&path("big.bin"), &vec![1; 64 * 1024]);
+
+    let first: Vec<MushLink> = scan_links(&[src.path()], dst.path())
+        .into_values()
+        .collect();
+    let header = ManifestHeader::new(&[src.path().to_str().unwrap().to_string()], "");
+    let since = |links: Vec<MushLink>| ScanOptions {
+        previous: Some((header.clone(), links)),
+        ..Default::default()
+    };
+
+    //Unchanged files take their hash from the previous manifest unread
+    let mut tampered = first.clone();
+    for link in tampered.iter_mut().filter(|l| l.src == path("keep.txt")) {
+        link.hash = String::from("recorded");
+    }
+    let links = scan_links_with(&[src.path()], dst.path(), &since(tampered));
+    assert_eq!(link_for(&links, &path("keep.txt")).hash, "recorded");
+
+    write_settled(&path("edit.txt"), b"edited");
+    fs::remove_file(path("gone.txt")).unwrap();
+    fs::rename(path("old_name.txt"), path("new_name.txt")).unwrap();
+    write(&path("fresh.txt"), b"fresh");
+    write(&path("big_copy.bin"), &vec![1; 64 * 1024]);
+
+    let describe = |links: HashMap<String, MushLink>| {
+        let mut links: Vec<String> = links.into_values().map(|l| l.to_string()).collect();
+        links.sort();
+        links
+    };
+    let incremental = scan_links_with(&[src.path()], dst.path(), &since(first.clone()));
+    let full = scan_links(&[src.path()], dst.path());
+    assert_eq!(describe(incremental.clone()), describe(full));
+
+    let current: Vec<MushLink> = incremental.into_values().collect();
+    let changes = source_changes(&first, &current);
+    assert_eq!(changes.unchanged, 2);
+    assert_eq!(changes.modified, vec![path("edit.txt")]);
+    assert_eq!(changes.deleted, vec![path("gone.txt")]);
+    assert_eq!(
+        changes.renamed,
+        vec![(path("old_name.txt"), path("new_name.txt"))]
+    );
+    let mut added: Vec<PathBuf> = changes.added;
+    added.sort();
+    assert_eq!(added, vec![path("big_copy.bin"), path("fresh.txt")]);
+}
+
+/// Scan a forged collision and return the link of whichever file was flagged
+fn scan_collision(collision: CollisionPolicy) -> (TempDir, MushLink) {
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("photo.jpg"), &original);
+    write(&src.path().join("sub/photo.jpg"), &forged);
+
+    let options = ScanOptions {
+        collision,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    let collided = links
+        .into_values()
+        .find(|l| l.action == MushAction::Collision)
+        .expect("Expected a collision");
+    (dst, collided)
+}
+
+#[test]
+fn collision_rename_adds_counter_suffix() {
+    let (_dst, link) = scan_collision(CollisionPolicy::Rename);
+    assert!(link.dst.ends_with("photo_1.jpg"));
+}
+
+#[test]
+fn collision_digest_adds_digest_suffix() {
+    let (_dst, link) = scan_collision(CollisionPolicy::Digest);
+    let name = Path::new(&link.dst).file_name().unwrap().to_str().unwrap();
+    assert_eq!(name.len(), "photo_12345678.jpg".len());
+    assert!(name.starts_with("photo_"));
+}
+
+#[test]
+fn collision_rehash_keeps_own_path() {
+    let (dst, link) = scan_collision(CollisionPolicy::Rehash);
+    assert!(link.hash.starts_with("blake3:"));
+    assert!(Path::new(&link.dst).starts_with(dst.path()));
+    assert!(link.dst.ends_with("photo.jpg"));
+}
+
+#[test]
+fn copies_of_a_colliding_file_are_skipped() {
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    write(&src.path().join("photo.jpg"), &original);
+    write(&src.path().join("sub/photo.jpg"), &forged);
+    write(&src.path().join("sub/zcopy.jpg"), &forged);
+
+    for collision in [
+        CollisionPolicy::Rename,
+        CollisionPolicy::Digest,
+        CollisionPolicy::Rehash,
+    ] {
+        let dst = TempDir::new().unwrap();
+        let options = ScanOptions {
+            collision,
+            ..Default::default()
+        };
+        let links = scan_links_with(&[src.path()], dst.path(), &options);
+        assert_eq!(links.len(), 3);
+        let collided = link_for(&links, &src.path().join("sub/photo.jpg"));
+        let copy = link_for(&links, &src.path().join("sub/zcopy.jpg"));
+        assert_eq!(collided.action, MushAction::Collision);
+        assert!(collided.hash.starts_with("blake3:"));
+        assert_eq!(copy.action, MushAction::Skip);
+        assert_eq!(copy.dst, collided.dst);
+    }
+}
+
+#[test]
+fn collision_digest_avoids_a_taken_name() {
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("photo.jpg"), &original);
+    write(&src.path().join("sub/photo.jpg"), &forged);
+    let options = ScanOptions {
+        collision: CollisionPolicy::Digest,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    let digested = link_for(&links, &src.path().join("sub/photo.jpg"))
+        .dst
+        .to_owned();
+
+    //A file already at the digest suffixed name is not overwritten
+    write(&digested, b"unrelated");
+    let links = scan_links_with(&[src.path()], dst.path(), &options);
+    let collided = link_for(&links, &src.path().join("sub/photo.jpg"));
+    assert_eq!(collided.action, MushAction::Collision);
+    let stem = digested.file_stem().unwrap().to_str().unwrap();
+    assert_eq!(
+        collided.dst,
+        digested.with_file_name(format!("{}_1.jpg", stem))
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn collision_rename_keeps_non_utf8_name() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let name = OsStr::from_bytes(b"caf\xe9.jpg");
+    write(&src.path().join(name), &original);
+    write(&src.path().join("sub").join(name), &forged);
+
+    let links = scan_links_with(&[src.path()], dst.path(), &ScanOptions::default());
+    let collided = link_for(&links, &src.path().join("sub").join(name));
+    assert_eq!(collided.action, MushAction::Collision);
+    let renamed = Path::new(&collided.dst).file_name().unwrap();
+    assert_eq!(renamed.as_bytes(), b"caf\xe9_1.jpg");
+}
+
+#[test]
+fn collision_halt_stops_scan() {
+    let (original, forged) = forge_collision();
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("one.bin"), &original);
+    write(&src.path().join("two.bin"), &forged);
+
+    let options = ScanOptions {
+        collision: CollisionPolicy::Halt,
+        ..Default::default()
+    };
+    let src = vec![src.path().to_str().unwrap().to_string()];
+    let dst = dst.path().to_str().unwrap().to_string();
+    assert!(scan(src, dst, &mut in_memory(), &options).is_err());
+}
+
+/// Scan two sources that both hold a different photo.jpg
+fn scan_path_conflict(
+    path_conflict: PathConflictPolicy,
+) -> (TempDir, TempDir, TempDir, HashMap<String, MushLink>) {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&first.path().join("photo.jpg"), b"first photo");
+    write(&second.path().join("photo.jpg"), b"second photo");
+    let older = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(first.path().join("photo.jpg"))
+        .unwrap()
+        .set_modified(older)
+        .unwrap();
+
+    let options = ScanOptions {
+        path_conflict,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[first.path(), second.path()], dst.path(), &options);
+    (first, second, dst, links)
+}
+
+#[test]
+fn path_conflict_rename_adds_counter_suffix() {
+    let (first, second, dst, links) = scan_path_conflict(PathConflictPolicy::Rename);
+    let kept = link_for(&links, &first.path().join("photo.jpg"));
+    let renamed = link_for(&links, &second.path().join("photo.jpg"));
+    assert_eq!(Path::new(&kept.dst), dst.path().join("photo.jpg"));
+    assert_eq!(Path::new(&renamed.dst), dst.path().join("photo_1.jpg"));
+    assert_eq!(renamed.action, MushAction::Add);
+}
+
+#[test]
+fn path_conflict_namespace_uses_source_root_name() {
+    let (first, second, dst, links) = scan_path_conflict(PathConflictPolicy::Namespace);
+    let kept = link_for(&links, &first.path().join("photo.jpg"));
+    let namespaced = link_for(&links, &second.path().join("photo.jpg"));
+    let root_name = second.path().file_name().unwrap();
+    assert_eq!(Path::new(&kept.dst), dst.path().join("photo.jpg"));
+    assert_eq!(
+        Path::new(&namespaced.dst),
+        dst.path().join(root_name).join("photo.jpg")
+    );
+}
+
+#[test]
+fn path_conflict_newest_ignores_older_file() {
+    let (first, second, dst, links) = scan_path_conflict(PathConflictPolicy::Newest);
+    let older = link_for(&links, &first.path().join("photo.jpg"));
+    let newer = link_for(&links, &second.path().join("photo.jpg"));
+    assert_eq!(older.action, MushAction::Ignore);
+    assert_eq!(newer.action, MushAction::Add);
+    assert_eq!(Path::new(&newer.dst), dst.path().join("photo.jpg"));
+}
+
+#[test]
+fn path_conflict_newest_moves_duplicates_of_older_file() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&first.path().join("photo.jpg"), b"first photo");
+    write(&first.path().join("zcopy.jpg"), b"first photo");
+    write(&first.path().join("zzcopy.jpg"), b"first photo");
+    write(&second.path().join("photo.jpg"), b"second photo");
+    let older = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(first.path().join("photo.jpg"))
+        .unwrap()
+        .set_modified(older)
+        .unwrap();
+
+    let options = ScanOptions {
+        path_conflict: PathConflictPolicy::Newest,
+        ..Default::default()
+    };
+    let links = scan_links_with(&[first.path(), second.path()], dst.path(), &options);
+
+    assert_eq!(links.len(), 4);
+    let older = link_for(&links, &first.path().join("photo.jpg"));
+    assert_eq!(older.action, MushAction::Ignore);
+    let newer = link_for(&links, &second.path().join("photo.jpg"));
+    let copy = link_for(&links, &first.path().join("zcopy.jpg"));
+    let other = link_for(&links, &first.path().join("zzcopy.jpg"));
+    assert_eq!(Path::new(&newer.dst), dst.path().join("photo.jpg"));
+    assert_eq!(copy.action, MushAction::Add);
+    assert_eq!(Path::new(&copy.dst), dst.path().join("zcopy.jpg"));
+    assert_eq!(other.action, MushAction::Skip);
+    assert_eq!(other.dst, copy.dst);
+}
+
+#[test]
+fn different_files_are_added() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    write(&src.path().join("one.txt"), b"one");
+    write(&src.path().join("two.txt"), b"two");
+
+    let links = scan_links(&[src.path()], dst.path());
+
+    assert_eq!(links.len(), 2);
+    assert!(links.values().all(|l| l.action == MushAction::Add));
+}
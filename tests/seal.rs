@@ -0,0 +1,127 @@
+mod common;
+
+use std::fs::{self, File};
+use std::path::Path;
+
+use common::{scan_into, to_file};
+use mush::{
+    check_signature, generate_key, read_manifest, seal, ManifestFormat, ScanOptions,
+    MANIFEST_VERSION,
+};
+use tempfile::TempDir;
+
+/// Scan two files into a manifest of the given format at path
+fn scan_to(path: &Path, format: ManifestFormat) -> (TempDir, TempDir) {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    fs::write(src.path().join("two.txt"), b"two").unwrap();
+    let manifest = to_file(path, format);
+    scan_into(manifest, &[src.path()], dst.path(), &ScanOptions::default());
+    (src, dst)
+}
+
+fn read(path: &Path, format: ManifestFormat) -> Result<usize, String> {
+    read_manifest(&File::open(path).unwrap(), format)
+        .map(|(_, links)| links.len())
+        .map_err(|e| e.message)
+}
+
+#[test]
+fn versions_without_a_trailer_are_refused() {
+    let work = TempDir::new().unwrap();
+    let path = work.path().join("manifest.mush");
+    let _dirs = scan_to(&path, ManifestFormat::Csv);
+    let contents = fs::read_to_string(&path).unwrap();
+
+    let body: String = contents
+        .lines()
+        .filter(|line| !line.starts_with("#trailer"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    for version in ["2", "3"] {
+        let downgraded = body
+            .replace(
+                &format!("#mush-manifest {}", MANIFEST_VERSION),
+                &format!("#mush-manifest {}", version),
+            )
+            .replace("dst:one.txt", "dst:evil.txt");
+        fs::write(&path, downgraded).unwrap();
+        let e = read(&path, ManifestFormat::Csv).unwrap_err();
+        assert!(e.contains("has no trailer"), "{}", e);
+    }
+
+    //Version 1 never had a header, so one cannot claim it
+    let claimed = body.replace(
+        &format!("#mush-manifest {}", MANIFEST_VERSION),
+        "#mush-manifest 1",
+    );
+    fs::write(&path, claimed).unwrap();
+    assert!(read(&path, ManifestFormat::Csv).is_err());
+}
+
+#[test]
+fn truncated_and_tampered_manifests_are_refused() {
+    let work = TempDir::new().unwrap();
+    for (name, format) in [
+        ("manifest.mush", ManifestFormat::Csv),
+        ("manifest.jsonl", ManifestFormat::Ndjson),
+        ("manifest.json", ManifestFormat::Json),
+        ("manifest.toml", ManifestFormat::Toml),
+    ] {
+        let path = work.path().join(name);
+        let _dirs = scan_to(&path, format);
+        assert_eq!(read(&path, format), Ok(2));
+        let contents = fs::read_to_string(&path).unwrap();
+
+        //Half copied, the trailer and the last entry are lost
+        let cut = contents.rfind("two.txt").unwrap();
+        fs::write(&path, &contents[..cut]).unwrap();
+        assert!(read(&path, format).is_err(), "{}", name);
+
+        let tampered = contents.replace("dst:one.txt", "dst:evil.txt");
+        fs::write(&path, tampered).unwrap();
+        let e = read(&path, format).unwrap_err();
+        assert!(e.contains("checksum does not match"), "{}: {}", name, e);
+
+        seal(&path, format, None).unwrap();
+        assert_eq!(read(&path, format), Ok(2));
+    }
+}
+
+#[test]
+fn json_manifest_is_one_document_holding_its_trailer() {
+    let work = TempDir::new().unwrap();
+    let path = work.path().join("manifest.json");
+    let _dirs = scan_to(&path, ManifestFormat::Json);
+    let document: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(document["trailer"]["entries"], 2);
+
+    //Only the content is sealed, not how it is laid out
+    fs::write(&path, serde_json::to_vec(&document).unwrap()).unwrap();
+    assert_eq!(read(&path, ManifestFormat::Json), Ok(2));
+}
+
+#[test]
+fn signed_manifest_is_checked_against_key() {
+    let work = TempDir::new().unwrap();
+    let path = work.path().join("manifest.mush");
+    let _dirs = scan_to(&path, ManifestFormat::Csv);
+    let key = work.path().join("mush.key");
+    let public = generate_key(&key).unwrap();
+    let other = generate_key(&work.path().join("other.key")).unwrap();
+
+    assert!(check_signature(&path, ManifestFormat::Csv, &public).is_err());
+    assert_eq!(seal(&path, ManifestFormat::Csv, Some(&key)).unwrap(), 2);
+    check_signature(&path, ManifestFormat::Csv, &public).unwrap();
+    assert_eq!(read(&path, ManifestFormat::Csv), Ok(2));
+
+    let e = check_signature(&path, ManifestFormat::Csv, &other).unwrap_err();
+    assert!(e.message.contains("another key"), "{}", e.message);
+
+    //Resealing without the key after an edit drops the signature
+    let contents = fs::read_to_string(&path).unwrap();
+    fs::write(&path, contents.replace("dst:one.txt", "dst:evil.txt")).unwrap();
+    seal(&path, ManifestFormat::Csv, None).unwrap();
+    assert!(check_signature(&path, ManifestFormat::Csv, &public).is_err());
+}
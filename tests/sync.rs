@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use mush::{
+    push, save_sync_state, sync, Manifest, MushAction, MushMode, PushOptions, UpdatePolicy,
+};
+use tempfile::TempDir;
+
+/// Plan a sync, apply it and save the state as the sync command does,
+/// returning the action planned for each relative path
+fn sync_once(src: &Path, dst: &Path) -> BTreeMap<String, MushAction> {
+    let (src, dst) = (src.to_str().unwrap(), dst.to_str().unwrap());
+    let mut manifest = Manifest::Map(HashMap::new());
+    sync(src, dst, &mut manifest);
+    let options = PushOptions {
+        mode: MushMode::Copy,
+        update: UpdatePolicy::Always,
+        trash: None,
+    };
+    let outcomes = push(&manifest, &options).unwrap();
+    save_sync_state(src, dst, &outcomes);
+    match manifest {
+        Manifest::Map(map) => map.into_iter().map(|(rel, l)| (rel, l.action)).collect(),
+        Manifest::File(..) => unreachable!(),
+    }
+}
+
+fn actions(pairs: &[(&str, MushAction)]) -> BTreeMap<String, MushAction> {
+    pairs
+        .iter()
+        .map(|(rel, action)| (rel.to_string(), action.clone()))
+        .collect()
+}
+
+#[test]
+fn changes_on_either_side_propagate() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let (a, b) = (src.path(), dst.path());
+    fs::write(a.join("both.txt"), "both").unwrap();
+    fs::write(b.join("both.txt"), "both").unwrap();
+    fs::write(a.join("from_src.txt"), "src").unwrap();
+    fs::write(b.join("from_dst.txt"), "dst").unwrap();
+
+    let planned = sync_once(a, b);
+    assert_eq!(
+        planned,
+        actions(&[
+            ("both.txt", MushAction::Skip),
+            ("from_dst.txt", MushAction::Retreive),
+            ("from_src.txt", MushAction::Add),
+        ])
+    );
+    assert_eq!(fs::read_to_string(b.join("from_src.txt")).unwrap(), "src");
+    assert_eq!(fs::read_to_string(a.join("from_dst.txt")).unwrap(), "dst");
+
+    fs::write(a.join("from_src.txt"), "src edited").unwrap();
+    fs::write(b.join("from_dst.txt"), "dst edited").unwrap();
+    fs::remove_file(a.join("both.txt")).unwrap();
+    let planned = sync_once(a, b);
+    assert_eq!(
+        planned,
+        actions(&[
+            ("both.txt", MushAction::Remove),
+            ("from_dst.txt", MushAction::Retreive),
+            ("from_src.txt", MushAction::Update),
+        ])
+    );
+    assert!(!b.join("both.txt").exists());
+    assert_eq!(
+        fs::read_to_string(b.join("from_src.txt")).unwrap(),
+        "src edited"
+    );
+    assert_eq!(
+        fs::read_to_string(a.join("from_dst.txt")).unwrap(),
+        "dst edited"
+    );
+
+    //Nothing left to do once both sides agree
+    let planned = sync_once(a, b);
+    assert!(planned.values().all(|action| *action == MushAction::Skip));
+}
+
+#[test]
+fn edits_on_both_sides_conflict() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let (a, b) = (src.path(), dst.path());
+    fs::write(a.join("shared.txt"), "base").unwrap();
+    sync_once(a, b);
+
+    fs::write(a.join("shared.txt"), "src side").unwrap();
+    fs::write(b.join("shared.txt"), "dst side").unwrap();
+    let planned = sync_once(a, b);
+    assert_eq!(planned, actions(&[("shared.txt", MushAction::Conflict)]));
+    assert_eq!(
+        fs::read_to_string(a.join("shared.txt")).unwrap(),
+        "src side"
+    );
+    assert_eq!(
+        fs::read_to_string(b.join("shared.txt")).unwrap(),
+        "dst side"
+    );
+
+    //The conflict stands until one side gives way
+    let planned = sync_once(a, b);
+    assert_eq!(planned, actions(&[("shared.txt", MushAction::Conflict)]));
+}
+
+#[test]
+fn file_deleted_on_both_sides_is_forgotten() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let (a, b) = (src.path(), dst.path());
+    fs::write(a.join("f.txt"), "same").unwrap();
+    sync_once(a, b);
+
+    fs::remove_file(a.join("f.txt")).unwrap();
+    fs::remove_file(b.join("f.txt")).unwrap();
+    assert!(sync_once(a, b).is_empty());
+
+    //Recreated with the content it had, it is new rather than a stale delete
+    fs::write(a.join("f.txt"), "same").unwrap();
+    let planned = sync_once(a, b);
+    assert_eq!(planned, actions(&[("f.txt", MushAction::Add)]));
+    assert!(a.join("f.txt").exists());
+    assert!(b.join("f.txt").exists());
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use common::{scan_into, to_file};
+use mush::{read_manifest, validate, HashType, Manifest, ManifestFormat, ScanOptions, Validation};
+use tempfile::TempDir;
+
+/// Scan src into a CSV manifest at path
+fn scan_to(src: &Path, dst: &Path, path: &Path) {
+    scan_with(src, dst, path, &ScanOptions::default());
+}
+
+fn scan_with(src: &Path, dst: &Path, path: &Path, options: &ScanOptions) {
+    scan_into(to_file(path, ManifestFormat::Csv), &[src], dst, options);
+}
+
+fn validate_file(path: &Path) -> Validation {
+    let manifest = Manifest::File(File::open(path).unwrap(), ManifestFormat::Csv);
+    validate(&manifest).unwrap()
+}
+
+#[test]
+fn fresh_manifest_passes() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    fs::write(src.path().join("copy.txt"), b"one").unwrap();
+    let path = work.path().join("manifest.mush");
+    scan_to(src.path(), dst.path(), &path);
+
+    let validation = validate_file(&path);
+    assert!(validation.passed(), "{}", validation);
+    assert_eq!(validation.checked, 2);
+}
+
+#[test]
+fn changed_and_missing_sources_fail() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("changed.txt"), b"one").unwrap();
+    fs::write(src.path().join("missing.txt"), b"two").unwrap();
+    let path = work.path().join("manifest.mush");
+    scan_to(src.path(), dst.path(), &path);
+
+    fs::write(src.path().join("changed.txt"), b"edited").unwrap();
+    fs::remove_file(src.path().join("missing.txt")).unwrap();
+    fs::write(dst.path().join("changed.txt"), b"appeared").unwrap();
+
+    let validation = validate_file(&path);
+    assert!(!validation.passed());
+    let report = validation.problems.join("\n");
+    assert!(
+        report.contains("changed.txt changed since the scan"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("missing.txt no longer exists"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains("changed.txt exists since the scan"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn duplicate_targets_and_bad_lines_fail() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    fs::write(src.path().join("two.txt"), b"two").unwrap();
+    let path = work.path().join("manifest.mush");
+    scan_to(src.path(), dst.path(), &path);
+
+    let edited = fs::read_to_string(&path)
+        .unwrap()
+        .replace("dst:two.txt,", "dst:one.txt,")
+        + "[~],1,/a,/b\n";
+    fs::write(&path, edited).unwrap();
+
+    let validation = validate_file(&path);
+    assert_eq!(validation.checked, 2);
+    let report = validation.problems.join("\n");
+    assert!(report.contains("unknown action [~]"), "{}", report);
+    assert!(
+        report.contains("one.txt is written by 2 entries"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn manifest_records_its_hash_algorithm() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    fs::write(src.path().join("one.txt"), b"one").unwrap();
+    fs::write(src.path().join("copy.txt"), b"one").unwrap();
+    let path = work.path().join("manifest.mush");
+
+    for hash in [HashType::Blake3, HashType::Sha256, HashType::Xxh3] {
+        let options = ScanOptions {
+            hash,
+            ..Default::default()
+        };
+        scan_with(src.path(), dst.path(), &path, &options);
+        let (header, _) = read_manifest(&File::open(&path).unwrap(), ManifestFormat::Csv).unwrap();
+        assert_eq!(header.hash_type().unwrap(), hash);
+
+        let validation = validate_file(&path);
+        assert!(validation.passed(), "{}: {}", hash, validation);
+
+        fs::write(src.path().join("one.txt"), b"two").unwrap();
+        assert!(!validate_file(&path).passed(), "{}", hash);
+        fs::write(src.path().join("one.txt"), b"one").unwrap();
+    }
+}
+
+#[test]
+fn edit_inside_large_file_fails() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    let video = src.path().join("video.bin");
+    fs::write(&video, vec![1; 64 * 1024]).unwrap();
+    let path = work.path().join("manifest.mush");
+    scan_to(src.path(), dst.path(), &path);
+    assert!(fs::read_to_string(&path).unwrap().contains("partial:"));
+
+    //Same size and ends, only the middle and the modified time differ
+    let mut edited = vec![1; 64 * 1024];
+    edited[32 * 1024] = 2;
+    fs::write(&video, edited).unwrap();
+    let later = SystemTime::now() + Duration::from_secs(60);
+    File::options()
+        .write(true)
+        .open(&video)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+
+    let report = validate_file(&path).problems.join("\n");
+    assert!(
+        report.contains("video.bin changed since the scan"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn edit_inside_large_file_keeping_its_mtime_fails() {
+    let src = TempDir::new().unwrap();
+    let dst = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+    let video = src.path().join("video.bin");
+    fs::write(&video, vec![1; 64 * 1024]).unwrap();
+    let path = work.path().join("manifest.mush");
+    //Only a full digest sees a change that keeps the size, ends and mtime
+    let options = ScanOptions {
+        full_digests: true,
+        ..Default::default()
+    };
+    scan_with(src.path(), dst.path(), &path, &options);
+    let scanned = fs::metadata(&video).unwrap().modified().unwrap();
+
+    //Same size, ends and modified time, only the middle differs
+    let mut edited = vec![1; 64 * 1024];
+    edited[32 * 1024] = 2;
+    fs::write(&video, edited).unwrap();
+    File::options()
+        .write(true)
+        .open(&video)
+        .unwrap()
+        .set_modified(scanned)
+        .unwrap();
+
+    let report = validate_file(&path).problems.join("\n");
+    assert!(
+        report.contains("video.bin changed since the scan"),
+        "{}",
+        report
+    );
+}